@@ -1,103 +1,310 @@
-use std::{error::Error, fmt, path::PathBuf, process::Command};
-
-use which::which;
+use std::path::{Path, PathBuf};
 
 use crate::{
     clean::remove_cache,
-    config::{expand_path, Config, VerboseOtion, PACKAGES_CACHE_PATH},
+    config::{expand_path, Config, VerboseOption, PACKAGES_CACHE_PATH},
+    error::{Error, ErrorKind, Result},
+    observer::{InstallEvent, InstallObserver},
     package::Package,
-    theme::{colorize, Type},
+    runner::{CommandRunner, Invocation, OutputMode},
+    snapshot::{verify_snapshot, Snapshots},
 };
 
-#[allow(dead_code)]
-#[derive(Debug)]
-enum BuildErrorType {
-    Dependency(String),
-    BuildProcess(String),
-}
-
-#[derive(Debug)]
-#[allow(dead_code)]
-struct BuildError(BuildErrorType, String, Option<Box<dyn std::error::Error>>);
+/// Exit code of makepkg when the package files already exist, such as when the cache is kept
+/// and the same version is installed again.
+const ALREADY_BUILT: i32 = 13;
 
-impl Error for BuildError {}
+/// Builds every package, then installs them all with a single `pacman -U`, so that nothing is
+/// installed unless every build succeeded.
+pub fn build_packages(
+    packages: &[&Package],
+    config: &Config,
+    runner: &dyn CommandRunner,
+    observer: &dyn InstallObserver,
+) -> Result<()> {
+    let result = build_and_install(packages, config, runner, observer);
 
-impl fmt::Display for BuildError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.1)
+    if !config.keep_cache {
+        remove_cache(packages.to_vec())?;
     }
+
+    result
 }
 
-pub fn build_packages(
-    packages: &Vec<&Package>,
+fn build_and_install(
+    packages: &[&Package],
     config: &Config,
-) -> Result<(), Box<dyn std::error::Error>> {
-    for package in packages.iter() {
-        println!("{} {}...", colorize(Type::Info, "Building"), &package.name);
-        let path = expand_path(PACKAGES_CACHE_PATH).join(&package.name);
-
-        build(package, &path, config)?;
-        eprintln!(
-            "{} installed {}",
-            colorize(Type::Success, "Successfully"),
-            package.name
-        );
+    runner: &dyn CommandRunner,
+    observer: &dyn InstallObserver,
+) -> Result<()> {
+    let snapshots = Snapshots::read();
+
+    let mut package_files = Vec::new();
+    for package in packages {
+        package_files.extend(build_package(
+            package, &snapshots, config, runner, observer,
+        )?);
     }
+
+    install_package_files(&package_files, config, runner)?;
+
+    packages
+        .iter()
+        .for_each(|package| observer.event(InstallEvent::Installed { package }));
     Ok(())
 }
 
-fn build(package: &Package, path: &PathBuf, config: &Config) -> Result<(), Box<dyn Error>> {
-    check_dependency("fakeroot")?;
-    check_dependency("make")?;
+fn build_package(
+    package: &Package,
+    snapshots: &Snapshots,
+    config: &Config,
+    runner: &dyn CommandRunner,
+    observer: &dyn InstallObserver,
+) -> Result<Vec<PathBuf>> {
+    // Never build something other than what was downloaded
+    verify_snapshot(&package.name, snapshots)?;
 
-    let no_confirm = match &config.no_confirm {
-        true => "--noconfirm",
-        false => "",
-    };
+    observer.event(InstallEvent::Building { package });
+    let path = expand_path(PACKAGES_CACHE_PATH).join(&package.name);
 
-    let (stdout, stderr) = config.get_verbose_config();
+    build(package, &path, config, runner)
+}
 
-    let exit_status = Command::new("makepkg")
-        .arg("-si")
-        .arg(no_confirm)
-        .stdout(stdout)
-        .stderr(stderr)
-        .current_dir(path)
-        .spawn()?
-        .wait_with_output()
-        .unwrap();
+/// Builds the package in `path`, returning the package files it produced.
+fn build(
+    package: &Package,
+    path: &Path,
+    config: &Config,
+    runner: &dyn CommandRunner,
+) -> Result<Vec<PathBuf>> {
+    check_dependency("fakeroot", runner)?;
+    check_dependency("make", runner)?;
 
-    if !config.keep_cache {
-        remove_cache(vec![package])?;
+    let mut invocation = Invocation::new("makepkg").arg("-s");
+    if config.no_confirm {
+        invocation = invocation.arg("--noconfirm");
     }
 
-    if exit_status.status.code().unwrap() != 0 {
+    let output = runner
+        .run(
+            &invocation
+                .current_dir(path)
+                .output(config.get_verbose_config()),
+        )
+        .map_err(|e| Error::new(ErrorKind::Build, "Failed to run makepkg").with_source(e))?;
+
+    if !output.is_success() && output.code != Some(ALREADY_BUILT) {
         let err_msg = match config.verbose {
-            VerboseOtion::Quiet => "Enable verbose and check above logs",
+            VerboseOption::Quiet => "Enable verbose and check above logs",
             _ => "Check above logs",
         };
         // TODO: Maybe make it non blocking
-        return Err(Box::new(BuildError(
-            BuildErrorType::BuildProcess(package.name.to_owned()),
-            format!(
-                "Makepkg failed to build package \"{}\". {}",
-                package.name,
-                err_msg
-            ),
-            None,
-        )));
+        return Err(Error::new(
+            ErrorKind::Build,
+            format!("Makepkg failed to build package \"{}\"", package.name),
+        )
+        .with_hint(err_msg));
+    }
+
+    let output = runner
+        .run(
+            &Invocation::new("makepkg")
+                .arg("--packagelist")
+                .current_dir(path),
+        )
+        .map_err(|e| Error::new(ErrorKind::Build, "Failed to run makepkg").with_source(e))?;
+
+    let package_files: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect();
+
+    if !output.is_success() || package_files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::Build,
+            format!("Failed to list the package files of \"{}\"", package.name),
+        ));
+    }
+
+    Ok(package_files)
+}
+
+/// Installs the built package files as one pacman transaction, through sudo like makepkg does.
+fn install_package_files(
+    package_files: &[PathBuf],
+    config: &Config,
+    runner: &dyn CommandRunner,
+) -> Result<()> {
+    let mut invocation = Invocation::new("sudo").args(["pacman", "-U"]);
+    if config.no_confirm {
+        invocation = invocation.arg("--noconfirm");
+    }
+
+    // pacman asks for confirmation, so its output is never hidden
+    let invocation = invocation
+        .args(package_files.iter().map(|file| file.display().to_string()))
+        .output((OutputMode::Inherit, OutputMode::Inherit));
+
+    let output = runner
+        .run(&invocation)
+        .map_err(|e| Error::new(ErrorKind::Pacman, "Failed to run pacman").with_source(e))?;
+
+    if !output.is_success() {
+        return Err(Error::new(
+            ErrorKind::Pacman,
+            "Pacman failed to install the built packages",
+        )
+        .with_hint("Check above logs"));
     }
 
     Ok(())
 }
 
-fn check_dependency(arg: &str) -> Result<(), Box<BuildError>> {
-    if let Err(_) = which(arg) {
-        return Err(Box::new(BuildError(
-            BuildErrorType::Dependency(arg.to_owned()),
+fn check_dependency(arg: &str, runner: &dyn CommandRunner) -> Result<()> {
+    if !runner.exists(arg) {
+        return Err(Error::new(
+            ErrorKind::Dependency,
             format!("Required dependency \"{}\" not found", arg),
-            None,
-        )));
+        )
+        .with_hint(format!("Install it with pacman -S {}", arg)));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::runner::{Output, OutputMode, RecordingRunner};
+
+    use super::*;
+
+    fn package() -> Package {
+        Package::new("yay".to_owned(), None, None, None, None, None)
+    }
+
+    fn config(no_confirm: bool) -> Config {
+        Config {
+            no_confirm,
+            keep_cache: true,
+            ..Config::default()
+        }
+    }
+
+    fn runner() -> RecordingRunner {
+        let runner = RecordingRunner::new();
+        runner.respond(
+            "makepkg",
+            &["--packagelist"],
+            Output::success(
+                "/tmp/aurme/yay/yay-12.3.5-1-x86_64.pkg.tar.zst\n\
+                 /tmp/aurme/yay/yay-debug-12.3.5-1-x86_64.pkg.tar.zst\n",
+            ),
+        );
+        runner
+    }
+
+    #[test]
+    fn makepkg_runs_in_the_package_folder() {
+        let runner = runner();
+        let path = PathBuf::from("/tmp/aurme/yay");
+
+        let package_files = build(&package(), &path, &config(false), &runner).unwrap();
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 2);
+        assert_eq!(invocations[0].program, "makepkg");
+        assert_eq!(invocations[0].args, ["-s"]);
+        assert_eq!(invocations[0].current_dir, Some(path.clone()));
+        assert_eq!(
+            (invocations[0].stdout, invocations[0].stderr),
+            (OutputMode::Capture, OutputMode::Inherit)
+        );
+        assert_eq!(invocations[1].args, ["--packagelist"]);
+        assert_eq!(invocations[1].current_dir, Some(path));
+        assert_eq!(
+            package_files,
+            [
+                PathBuf::from("/tmp/aurme/yay/yay-12.3.5-1-x86_64.pkg.tar.zst"),
+                PathBuf::from("/tmp/aurme/yay/yay-debug-12.3.5-1-x86_64.pkg.tar.zst"),
+            ]
+        );
+    }
+
+    #[test]
+    fn makepkg_does_not_confirm_with_no_confirm() {
+        let runner = runner();
+
+        build(&package(), &PathBuf::from("yay"), &config(true), &runner).unwrap();
+
+        assert_eq!(runner.invocations()[0].args, ["-s", "--noconfirm"]);
+    }
+
+    #[test]
+    fn already_built_packages_are_reused() {
+        let runner = runner();
+        runner.respond("makepkg", &["-s"], Output::failure(ALREADY_BUILT));
+
+        let package_files = build(&package(), &PathBuf::from("yay"), &config(false), &runner);
+
+        assert_eq!(package_files.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn failed_builds_fail() {
+        let runner = runner();
+        runner.respond("makepkg", &["-s"], Output::failure(4));
+
+        let error = build(&package(), &PathBuf::from("yay"), &config(false), &runner).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Build);
+        assert_eq!(runner.invocations().len(), 1);
+    }
+
+    #[test]
+    fn missing_build_dependencies_fail_before_makepkg() {
+        let runner = RecordingRunner::new();
+        runner.set_missing("fakeroot");
+
+        let error = build(&package(), &PathBuf::from("yay"), &config(false), &runner).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Dependency);
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[test]
+    fn package_files_are_installed_in_one_transaction() {
+        let runner = RecordingRunner::new();
+        let package_files = [
+            PathBuf::from("/tmp/aurme/yay/yay-12.3.5-1-x86_64.pkg.tar.zst"),
+            PathBuf::from("/tmp/aurme/paru/paru-2.0.3-1-x86_64.pkg.tar.zst"),
+        ];
+
+        install_package_files(&package_files, &config(true), &runner).unwrap();
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].program, "sudo");
+        assert_eq!(
+            invocations[0].args,
+            [
+                "pacman",
+                "-U",
+                "--noconfirm",
+                "/tmp/aurme/yay/yay-12.3.5-1-x86_64.pkg.tar.zst",
+                "/tmp/aurme/paru/paru-2.0.3-1-x86_64.pkg.tar.zst",
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_installs_fail() {
+        let runner = RecordingRunner::new();
+        runner.respond("sudo", &["pacman", "-U"], Output::failure(1));
+
+        let error =
+            install_package_files(&[PathBuf::from("yay.pkg.tar.zst")], &config(false), &runner)
+                .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Pacman);
+    }
+}
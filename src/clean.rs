@@ -1,17 +1,19 @@
 use crate::config::{expand_path, PACKAGES_CACHE_PATH};
+use crate::error::Result;
+use crate::json::{print_json, JsonCacheEntry};
 use crate::package::Package;
+use crate::snapshot::{Snapshots, SNAPSHOTS_PATH};
 use crate::theme::{colorize, Type};
-use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-pub fn handle_clean(packages: &[&str]) -> Result<(), Box<dyn Error>> {
+pub fn handle_clean(packages: &[&str]) -> Result<()> {
     let cache_path: PathBuf = expand_path(PACKAGES_CACHE_PATH);
 
     if !cache_path.exists() {
         return Ok(());
     }
 
-    if packages.len() == 0 {
+    if packages.is_empty() {
         remove_all_cache(&cache_path)?;
         return Ok(());
     }
@@ -24,7 +26,7 @@ pub fn handle_clean(packages: &[&str]) -> Result<(), Box<dyn Error>> {
         }
     }
 
-    if packages_deleted.len() == 0 {
+    if packages_deleted.is_empty() {
         return Ok(());
     }
 
@@ -37,15 +39,52 @@ pub fn handle_clean(packages: &[&str]) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn remove_cache(packages: Vec<&Package>) -> Result<(), Box<dyn Error>> {
+pub fn handle_list_cache(json: bool) -> Result<()> {
+    let cache_path: PathBuf = expand_path(PACKAGES_CACHE_PATH);
+
+    let mut names: Vec<String> = Vec::new();
+    if cache_path.exists() {
+        for entry in std::fs::read_dir(&cache_path)? {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+            let name = file_name.trim_end_matches(".tar.gz").to_owned();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    let mut entries: Vec<JsonCacheEntry> = names
+        .into_iter()
+        .map(|name| JsonCacheEntry {
+            path: cache_path.join(&name).display().to_string(),
+            snapshot: cache_path.join(format!("{}.tar.gz", name)).exists(),
+            extracted: cache_path.join(&name).is_dir(),
+            name,
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        return print_json(&entries);
+    }
+
+    entries.iter().for_each(|entry| {
+        println!("{} {}", colorize(Type::Header, &entry.name), entry.path);
+    });
+
+    Ok(())
+}
+
+pub fn remove_cache(packages: Vec<&Package>) -> Result<()> {
     for package in packages.iter() {
         remove(&package.name)?;
     }
     Ok(())
 }
 
-fn remove_all_cache(cache_path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    std::fs::remove_dir_all(&cache_path)?;
+fn remove_all_cache(cache_path: &PathBuf) -> Result<()> {
+    std::fs::remove_dir_all(cache_path)?;
+    let _ = std::fs::remove_file(expand_path(SNAPSHOTS_PATH));
     println!(
         "{} cleared cache of all packages",
         colorize(Type::Success, "Successfully"),
@@ -53,15 +92,18 @@ fn remove_all_cache(cache_path: &PathBuf) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn remove(package: &str) -> Result<(), Box<dyn Error>> {
+fn remove(package: &str) -> Result<()> {
     let cache_path: PathBuf = expand_path(PACKAGES_CACHE_PATH);
     let package_path = cache_path.join(package);
     std::fs::remove_file(format!("{}.tar.gz", package_path.display()))?;
     std::fs::remove_dir_all(package_path)?;
-    Ok(())
+
+    let mut snapshots = Snapshots::read();
+    snapshots.remove(package);
+    snapshots.write()
 }
 
-fn check_if_cache_exists(cache_path: &PathBuf, package: &str) -> bool {
+fn check_if_cache_exists(cache_path: &Path, package: &str) -> bool {
     let tar_name = format!("{}.tar.gz", package);
     let tar_path = cache_path.join(tar_name);
     let package_path = cache_path.join(package);
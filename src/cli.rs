@@ -1,14 +1,37 @@
-use std::{
-    error::Error,
-    io::{stdin, stdout, Write},
-};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{stderr, stdin, stdout, IsTerminal, Write};
+use std::sync::Mutex;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 
 use crate::{
+    config::Config,
+    conflicts::{Conflict, ConflictReason},
+    error::{Error, ErrorKind, Result},
+    observer::{InstallEvent, InstallObserver},
     package::Package,
+    pgp::PgpKey,
+    query::Upgrade,
+    sync::DatabaseChanges,
     theme::{colorize, Type},
+    version::vercmp,
 };
 
-pub fn print_outdated_packages(outdated: &[(&Package, &Package)]) {
+pub fn print_warning(message: &str) {
+    eprintln!("{} {}", colorize(Type::Warning, "Warning:"), message);
+}
+
+/// Prints a message that isn't part of the output, to stderr with `--json` so that stdout stays
+/// valid JSON.
+pub fn print_status(json: bool, message: &str) {
+    match json {
+        true => eprintln!("{}", message),
+        false => println!("{}", message),
+    }
+}
+
+pub fn print_outdated_packages(outdated: &[Upgrade]) {
     println!(
         "{}",
         colorize(
@@ -17,65 +40,541 @@ pub fn print_outdated_packages(outdated: &[(&Package, &Package)]) {
         )
     );
 
-    outdated.iter().for_each(|(local, db)| {
+    outdated.iter().for_each(|upgrade| {
         println!(
             "   {} ({} -> {})",
-            local.name,
-            colorize(Type::Error, &local.version),
-            colorize(Type::Success, &db.version),
+            upgrade.installed.name,
+            colorize(Type::Error, &upgrade.installed.version),
+            colorize(Type::Success, &upgrade.available.version),
+        );
+    });
+}
+
+/// Annotates a package with `[installed]`, or `[installed: <version>]` if the installed version
+/// differs from the AUR one, followed by `[outdated]` when the AUR one is newer.
+fn get_installed_status(
+    package: &Package,
+    installed_packages: &[Package],
+    colored: bool,
+) -> String {
+    let paint = |style: Type, string: &str| match colored {
+        true => colorize(style, string),
+        false => string.to_owned(),
+    };
+
+    let Some(installed) = installed_packages.iter().find(|p| p.name == package.name) else {
+        return String::new();
+    };
+    let installed_version = paint(
+        Type::Warning,
+        &format!("[installed: {}]", installed.version),
+    );
+
+    match vercmp(&installed.version, &package.version) {
+        Ordering::Equal => format!(" {}", paint(Type::Success, "[installed]")),
+        Ordering::Less => format!(
+            " {} {}",
+            installed_version,
+            paint(Type::Error, "[outdated]")
+        ),
+        Ordering::Greater => format!(" {}", installed_version),
+    }
+}
+
+pub fn print_package_info(package: &Package, installed_packages: &[Package], config: &Config) {
+    let list = |values: &Option<Vec<String>>| match values {
+        Some(values) if !values.is_empty() => values.join("  "),
+        _ => String::from("None"),
+    };
+
+    [
+        ("Repository", String::from("aur")),
+        ("Name", package.name.to_owned()),
+        ("Version", package.version.to_owned()),
+        ("Description", package.get_description().to_owned()),
+        ("URL", package.get_url_path(config)),
+        ("Keywords", list(&package.keywords)),
+        ("Provides", list(&package.provides)),
+        ("Depends On", list(&package.depends)),
+        ("Make Deps", list(&package.make_depends)),
+        ("Optional Deps", list(&package.opt_depends)),
+        ("Conflicts With", list(&package.conflicts)),
+        ("Replaces", list(&package.replaces)),
+        (
+            "Maintainer",
+            package
+                .maintainer
+                .to_owned()
+                .unwrap_or(String::from("None")),
+        ),
+        ("Votes", package.num_votes.to_string()),
+        ("Popularity", format!("{:.2}", package.popularity)),
+        (
+            "Installed",
+            match get_installed_status(package, installed_packages, true).as_str() {
+                "" => String::from("No"),
+                status => status.trim().to_owned(),
+            },
+        ),
+    ]
+    .iter()
+    .for_each(|(field, value)| {
+        println!(
+            "{} : {}",
+            colorize(Type::Header, &format!("{:<15}", field)),
+            value
         );
     });
+    println!();
 }
 
 pub fn get_yes_no(question: &str) -> bool {
-    print!("\n{} [Y/n]:", question);
+    let input = read_answer(question, "[Y/n]");
+    input.is_empty() || input == "y" || input == "Y"
+}
+
+/// Like `get_yes_no`, but an empty answer or no input at all, e.g. from `</dev/null`, means no.
+pub fn get_no_yes(question: &str) -> bool {
+    let input = read_answer(question, "[y/N]");
+    input == "y" || input == "Y"
+}
+
+fn read_answer(question: &str, choices: &str) -> String {
+    print!("\n{} {}:", question, choices);
     stdout().flush().unwrap();
 
     let mut input = String::new();
     stdin().read_line(&mut input).unwrap();
 
-    let input = input.trim();
-
-    input == "" || input == "y" || input == "Y"
+    input.trim().to_owned()
 }
 
-pub fn print_top_packages(packages: &[&Package]) {
+pub fn print_top_packages(packages: &[Package], installed_packages: &[Package]) {
     packages.iter().rev().enumerate().for_each(|(i, package)| {
         println!(
-            "\n{} {}\n  {}",
+            "\n{} {}{}\n  {}",
             colorize(Type::Info, format!("{} ┃", packages.len() - i).as_str()),
             colorize(Type::Header, package.name.as_str()),
+            get_installed_status(package, installed_packages, true),
+            package.get_description()
+        );
+    });
+}
+
+/// Prints search results the way `pacman -Ss` does, without colors when not on a terminal.
+pub fn print_search_results(packages: &[Package], installed_packages: &[Package]) {
+    let is_terminal = stdout().is_terminal();
+    let paint = |style: Type, string: &str| match is_terminal {
+        true => colorize(style, string),
+        false => string.to_owned(),
+    };
+
+    packages.iter().for_each(|package| {
+        println!(
+            "{}{} {} (+{} {:.2}){}\n    {}",
+            paint(Type::Warning, "aur/"),
+            paint(Type::Header, &package.name),
+            paint(Type::Success, &package.version),
+            package.num_votes,
+            package.popularity,
+            get_installed_status(package, installed_packages, is_terminal),
             package.get_description()
         );
     });
 }
 
-pub fn get_value_from_range<'a>(
-    message: &str,
-    min: usize,
-    max: usize,
-) -> Result<Option<usize>, Box<dyn Error>> {
-    print!("\n{} ({}-{}) or (q)uit: ", message, min, max);
+pub fn print_database_changes(changes: &DatabaseChanges, json: bool) {
+    let print_change = |label: &str, names: Vec<&str>| {
+        if names.is_empty() {
+            return;
+        }
+
+        let listed = match names.len() <= 10 {
+            true => format!(": {}", names.join(", ")),
+            false => String::new(),
+        };
+
+        print_status(
+            json,
+            &format!(
+                "   {} {}{}",
+                colorize(Type::Header, &names.len().to_string()),
+                label,
+                listed
+            ),
+        );
+    };
+
+    print_change("new", changes.new.iter().map(String::as_str).collect());
+    print_change(
+        "removed",
+        changes.removed.iter().map(String::as_str).collect(),
+    );
+    print_change(
+        "updated",
+        changes
+            .updated
+            .iter()
+            .map(|(name, _, _)| name.as_str())
+            .collect(),
+    );
+    print_change(
+        "newly orphaned",
+        changes.orphaned.iter().map(String::as_str).collect(),
+    );
+    print_change(
+        "newly flagged out of date",
+        changes.flagged.iter().map(String::as_str).collect(),
+    );
+}
+
+/// Prompts for a selection such as `1 3 5-7`, `^4` or `a`. Returns `None` if the user quits.
+pub fn get_values_from_range(message: &str, min: usize, max: usize) -> Result<Option<Vec<usize>>> {
+    print!("\n{} (eg: 1 2 3, 1-3, ^4 or (a)ll) or (q)uit: ", message);
     stdout().flush()?;
 
     let mut input = String::new();
     stdin().read_line(&mut input)?;
-    input = input.trim().to_string();
+    let input = input.trim();
 
-    if input == "q" || input == "quit" || input == "" {
+    if input == "q" || input == "quit" || input.is_empty() {
         return Ok(None);
     }
 
-    let parsed_input = input.parse::<usize>().unwrap_or(0);
+    parse_selection(input, min, max)
+        .map(Some)
+        .map_err(|e| Error::new(ErrorKind::InvalidArgument, e))
+}
+
+/// Parses a pacman style selection. Exclusions (`^n` or `^n-m`) are applied after inclusions,
+/// and a selection made only of exclusions starts from the whole range.
+fn parse_selection(input: &str, min: usize, max: usize) -> Result<Vec<usize>, String> {
+    let mut included: Vec<usize> = Vec::new();
+    let mut excluded: Vec<usize> = Vec::new();
+    let mut has_inclusions = false;
 
-    if parsed_input < min || parsed_input > max {
-        return Err(format!(
-            "Input out of range must be between [{}; {}]",
-            min,
-            max
-        )
-        .into());
+    for token in input
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+    {
+        if token == "a" || token == "all" {
+            has_inclusions = true;
+            included.extend(min..=max);
+            continue;
+        }
+
+        match token.strip_prefix('^') {
+            Some(exclusion) => excluded.extend(parse_range(exclusion, min, max)?),
+            None => {
+                has_inclusions = true;
+                included.extend(parse_range(token, min, max)?);
+            }
+        }
+    }
+
+    if !has_inclusions {
+        included.extend(min..=max);
+    }
+
+    let mut selection: Vec<usize> = Vec::new();
+    included.into_iter().for_each(|value| {
+        if !excluded.contains(&value) && !selection.contains(&value) {
+            selection.push(value);
+        }
+    });
+
+    if selection.is_empty() {
+        return Err("Selection is empty".to_owned());
+    }
+
+    Ok(selection)
+}
+
+fn parse_range(token: &str, min: usize, max: usize) -> Result<Vec<usize>, String> {
+    let parse_value = |value: &str| -> Result<usize, String> {
+        let parsed = value
+            .parse::<usize>()
+            .map_err(|_| format!("\"{}\" is not a valid number", value))?;
+
+        if parsed < min || parsed > max {
+            return Err(format!(
+                "{} is out of range, must be between [{}; {}]",
+                parsed, min, max
+            ));
+        }
+
+        Ok(parsed)
+    };
+
+    match token.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse_value(start)?, parse_value(end)?);
+            if start > end {
+                return Err(format!("Invalid range \"{}\", start is after end", token));
+            }
+            Ok((start..=end).collect())
+        }
+        None => Ok(vec![parse_value(token)?]),
+    }
+}
+
+/// Progress bars of concurrent downloads, one per package plus an overall one. Nothing is drawn
+/// when stderr isn't a terminal, messages are then printed as plain lines.
+pub struct DownloadProgress {
+    multi: Option<MultiProgress>,
+    overall: ProgressBar,
+}
+
+impl DownloadProgress {
+    pub fn new(count: usize) -> DownloadProgress {
+        if !stderr().is_terminal() {
+            return DownloadProgress {
+                multi: None,
+                overall: ProgressBar::hidden(),
+            };
+        }
+
+        let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+        let overall = multi.add(ProgressBar::new(count as u64));
+        overall.set_style(
+            ProgressStyle::with_template(":: Downloading [{bar:30}] {pos}/{len}")
+                .expect("valid template")
+                .progress_chars("#>-"),
+        );
+
+        DownloadProgress {
+            multi: Some(multi),
+            overall,
+        }
+    }
+
+    /// Adds the bar of a package, to be updated with the downloaded and total bytes.
+    pub fn add(&self, name: &str) -> ProgressBar {
+        let multi = match &self.multi {
+            Some(multi) => multi,
+            None => return ProgressBar::hidden(),
+        };
+
+        let bar = multi.insert_before(&self.overall, ProgressBar::no_length());
+        bar.set_style(download_style());
+        bar.set_message(name.to_owned());
+        bar
+    }
+
+    /// Removes the bar of a finished package, printing `message` in its place.
+    pub fn finish(&self, bar: &ProgressBar, message: &str) {
+        self.overall.inc(1);
+        match &self.multi {
+            Some(multi) => {
+                bar.finish_and_clear();
+                multi.remove(bar);
+                let _ = multi.println(message);
+            }
+            None => eprintln!("{}", message),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.overall.finish_and_clear();
+    }
+}
+
+/// Prints the progress of installs and prompts for their confirmations.
+#[derive(Default)]
+pub struct CliObserver {
+    /// Bars of the running downloads, by package name.
+    downloads: Mutex<Option<(DownloadProgress, HashMap<String, ProgressBar>)>>,
+}
+
+impl CliObserver {
+    fn finish_download(&self, package: &Package, message: String) {
+        let downloads = self.downloads.lock().unwrap();
+        if let Some((progress, bars)) = downloads.as_ref() {
+            if let Some(bar) = bars.get(&package.name) {
+                progress.finish(bar, &message);
+            }
+        }
+    }
+}
+
+impl InstallObserver for CliObserver {
+    fn event(&self, event: InstallEvent) {
+        match event {
+            InstallEvent::DownloadsStarted { count } => {
+                *self.downloads.lock().unwrap() =
+                    Some((DownloadProgress::new(count), HashMap::new()))
+            }
+            InstallEvent::DownloadStarted { package } => {
+                if let Some((progress, bars)) = self.downloads.lock().unwrap().as_mut() {
+                    bars.insert(package.name.to_owned(), progress.add(&package.name));
+                }
+            }
+            InstallEvent::DownloadProgress {
+                package,
+                downloaded,
+                total,
+            } => {
+                if let Some((_, bars)) = self.downloads.lock().unwrap().as_ref() {
+                    if let Some(bar) = bars.get(&package.name) {
+                        if let Some(total) = total {
+                            bar.set_length(total);
+                        }
+                        bar.set_position(downloaded);
+                    }
+                }
+            }
+            InstallEvent::Downloaded { package, cached } => {
+                let message = match cached {
+                    true => format!(
+                        "{} cached snapshot of {}",
+                        colorize(Type::Success, "Using"),
+                        package.name
+                    ),
+                    false => format!(
+                        "{} downloaded {}",
+                        colorize(Type::Success, "Successfully"),
+                        package.name
+                    ),
+                };
+                self.finish_download(package, message);
+            }
+            InstallEvent::DownloadFailed { package } => self.finish_download(
+                package,
+                format!(
+                    "{} to download {}",
+                    colorize(Type::Error, "Failed"),
+                    package.name
+                ),
+            ),
+            InstallEvent::DownloadsFinished => {
+                if let Some((progress, _)) = self.downloads.lock().unwrap().take() {
+                    progress.clear();
+                }
+            }
+            InstallEvent::ImportingKeys { .. } => {
+                println!("{} PGP keys...", colorize(Type::Info, "Importing"))
+            }
+            InstallEvent::KeysImported { count } => eprintln!(
+                "{} imported {} PGP key(s)",
+                colorize(Type::Success, "Successfully"),
+                count
+            ),
+            InstallEvent::Building { package } => {
+                println!("{} {}...", colorize(Type::Info, "Building"), &package.name)
+            }
+            InstallEvent::Installed { package } => eprintln!(
+                "{} installed {}",
+                colorize(Type::Success, "Successfully"),
+                package.name
+            ),
+            InstallEvent::Warning(message) => print_warning(message),
+        }
+    }
+
+    fn confirm_conflicts(&self, conflicts: &[Conflict]) -> bool {
+        println!(
+            "{}",
+            colorize(
+                Type::Header,
+                format!("Conflicting packages ({}) ", conflicts.len()).as_str()
+            )
+        );
+        conflicts.iter().for_each(|conflict| {
+            let reason = match conflict.reason {
+                ConflictReason::Conflicts => "conflicts with",
+                ConflictReason::Replaces => "replaces",
+            };
+            println!(
+                "   {} {} {} ({})",
+                conflict.package,
+                reason,
+                colorize(Type::Warning, &conflict.installed.name),
+                conflict.installed.version
+            );
+        });
+
+        get_no_yes("Replace the installed packages and proceed?")
+    }
+
+    fn confirm_key_import(&self, keys: &[PgpKey]) -> bool {
+        println!(
+            "{}",
+            colorize(
+                Type::Header,
+                format!("Missing PGP keys ({}) ", keys.len()).as_str()
+            )
+        );
+        keys.iter().for_each(|key| {
+            println!("   {} ({})", key.key, colorize(Type::Info, &key.package));
+        });
+
+        get_yes_no("Import PGP keys?")
+    }
+}
+
+/// Bar of a single download of `len` bytes, hidden when stderr isn't a terminal.
+pub fn download_bar(name: &str, len: Option<u64>) -> ProgressBar {
+    if !stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let bar = ProgressBar::with_draw_target(len, ProgressDrawTarget::stderr());
+    bar.set_style(download_style());
+    bar.set_message(name.to_owned());
+    bar
+}
+
+fn download_style() -> ProgressStyle {
+    ProgressStyle::with_template(" {msg:30!} [{bar:30}] {bytes:>10}/{total_bytes:<10}")
+        .expect("valid template")
+        .progress_chars("#>-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selections_are_parsed() {
+        assert_eq!(parse_selection("2", 1, 10), Ok(vec![2]));
+        assert_eq!(parse_selection("1 3 5-7", 1, 10), Ok(vec![1, 3, 5, 6, 7]));
+        assert_eq!(parse_selection("3,1, 3", 1, 10), Ok(vec![3, 1]));
+        assert_eq!(parse_selection("a", 1, 3), Ok(vec![1, 2, 3]));
+        assert_eq!(parse_selection("all ^2", 1, 3), Ok(vec![1, 3]));
+    }
+
+    #[test]
+    fn exclusions_apply_after_inclusions() {
+        assert_eq!(parse_selection("^2", 1, 4), Ok(vec![1, 3, 4]));
+        assert_eq!(parse_selection("^2-3", 1, 4), Ok(vec![1, 4]));
+        assert_eq!(parse_selection("^1 1-3", 1, 4), Ok(vec![2, 3]));
     }
 
-    Ok(Some(parsed_input))
+    #[test]
+    fn invalid_selections_are_explained() {
+        assert_eq!(
+            parse_selection("x", 1, 10),
+            Err("\"x\" is not a valid number".to_owned())
+        );
+        assert_eq!(
+            parse_selection("11", 1, 10),
+            Err("11 is out of range, must be between [1; 10]".to_owned())
+        );
+        assert_eq!(
+            parse_selection("^0", 1, 10),
+            Err("0 is out of range, must be between [1; 10]".to_owned())
+        );
+        assert_eq!(
+            parse_selection("5-3", 1, 10),
+            Err("Invalid range \"5-3\", start is after end".to_owned())
+        );
+        assert_eq!(
+            parse_selection("1-", 1, 10),
+            Err("\"\" is not a valid number".to_owned())
+        );
+        assert_eq!(
+            parse_selection("^1-2", 1, 2),
+            Err("Selection is empty".to_owned())
+        );
+    }
 }
@@ -0,0 +1,124 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    config::{Config, CONFIG_PATH},
+    database::{download_database, get_installed_packages},
+    error::{Error, ErrorKind, Result},
+    install::{install, plan_install, plan_packages, InstallPlan},
+    observer::{InstallObserver, Warn},
+    package::Package,
+    query::{get_packages, get_upgrades, search, SearchOptions, SearchResults, Upgrades},
+    runner::{CommandRunner, SystemRunner},
+    sync::{SyncOutcome, SyncState},
+};
+
+type WarningHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Entry point of the library, doing what the `aurme` commands do without printing or prompting.
+/// Progress is reported through callbacks instead.
+pub struct Aurme {
+    config: Config,
+    runner: Arc<dyn CommandRunner>,
+    on_warning: WarningHandler,
+}
+
+impl Aurme {
+    pub fn new(config: Config) -> Aurme {
+        Aurme::with_runner(config, Arc::new(SystemRunner))
+    }
+
+    /// Runs pacman, makepkg and gpg through `runner`, e.g. a `RecordingRunner` to try the
+    /// install and upgrade flows without the Arch tooling.
+    pub fn with_runner(config: Config, runner: Arc<dyn CommandRunner>) -> Aurme {
+        Aurme {
+            config,
+            runner,
+            on_warning: Arc::new(|_| {}),
+        }
+    }
+
+    /// Uses the config of the `aurme` command, creating the default one if there is none.
+    /// `on_warning` is set as with [`Aurme::on_warning`], and also told if the config was reset.
+    pub fn from_config_file(on_warning: impl Fn(&str) + Send + Sync + 'static) -> Result<Aurme> {
+        let config = Config::read(CONFIG_PATH, &on_warning).map_err(|e| {
+            Error::new(ErrorKind::Config, "Error getting config file").with_source(e)
+        })?;
+        Ok(Aurme::new(config).on_warning(on_warning))
+    }
+
+    /// Calls `on_warning` with the warnings of operations that carry on anyway, such as retried
+    /// requests or mirror fallbacks. They are ignored by default. Warnings of an install go to
+    /// its observer instead.
+    pub fn on_warning(mut self, on_warning: impl Fn(&str) + Send + Sync + 'static) -> Aurme {
+        self.on_warning = Arc::new(on_warning);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn runner(&self) -> &dyn CommandRunner {
+        self.runner.as_ref()
+    }
+
+    /// Syncs the package database, like `aurme -Sy`. `progress` is called with the downloaded
+    /// and total bytes.
+    pub async fn sync(&self, progress: impl Fn(u64, Option<u64>) + Sync) -> Result<SyncOutcome> {
+        download_database(&self.config, &progress, self.warn()).await
+    }
+
+    /// Time since the package database was last synced, `None` if it never was.
+    pub fn database_age(&self) -> Option<Duration> {
+        SyncState::read().database_age()
+    }
+
+    pub async fn search(
+        &self,
+        search_term: &str,
+        options: &SearchOptions,
+    ) -> Result<SearchResults> {
+        search(search_term, options, &self.config, self.warn()).await
+    }
+
+    /// Looks up the packages named `names`, returning those found in the order of `names` and
+    /// the names that weren't.
+    pub async fn info<'a>(&self, names: &[&'a str]) -> Result<(Vec<Package>, Vec<&'a str>)> {
+        get_packages(names, &self.config, self.warn()).await
+    }
+
+    /// Foreign packages installed with pacman, with only their name and version set.
+    pub fn installed_packages(&self) -> Result<Vec<Package>> {
+        get_installed_packages(self.runner())
+    }
+
+    /// Outdated installed packages out of `names`, or out of all of them if `names` is empty.
+    pub async fn upgrades(&self, names: &[&str]) -> Result<Upgrades> {
+        get_upgrades(names, &self.config, self.runner(), self.warn()).await
+    }
+
+    pub async fn plan_install(&self, names: &[&str]) -> Result<InstallPlan> {
+        plan_install(names, &self.config, self.runner(), self.warn()).await
+    }
+
+    pub fn plan_upgrades(&self, upgrades: &Upgrades) -> Result<InstallPlan> {
+        plan_packages(
+            upgrades
+                .outdated
+                .iter()
+                .map(|upgrade| upgrade.available.clone())
+                .collect(),
+            self.runner(),
+        )
+    }
+
+    /// Downloads and builds the packages of `plan`, then installs them in one `pacman -U`. Both
+    /// makepkg and pacman run through sudo and may ask for a password on the terminal.
+    pub async fn install(&self, plan: &InstallPlan, observer: &dyn InstallObserver) -> Result<()> {
+        install(plan, &self.config, self.runner(), observer).await
+    }
+
+    fn warn(&self) -> Warn<'_> {
+        self.on_warning.as_ref()
+    }
+}
@@ -1,4 +1,4 @@
-use clap::{Arg, ArgAction, Command};
+use clap::{value_parser, Arg, ArgAction, Command};
 
 pub fn build_lookup_command() -> Command {
     Command::new("lookup")
@@ -12,6 +12,20 @@ pub fn build_lookup_command() -> Command {
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::new("ignore-case")
+                .long("ignore-case")
+                .short('i')
+                .action(ArgAction::SetTrue)
+                .help("ignore case when matching"),
+        )
+        .arg(
+            Arg::new("substring")
+                .long("substring")
+                .short('s')
+                .action(ArgAction::SetTrue)
+                .help("match the word anywhere in the package name"),
+        )
 }
 
 pub fn build_sync_command() -> Command {
@@ -24,14 +38,86 @@ pub fn build_sync_command() -> Command {
                 .short('s')
                 .long("search")
                 .help("search remote repositories for matching strings")
-                .conflicts_with_all(&["info", "sysupgrade", "refresh"])
+                .conflicts_with_all(["info", "sysupgrade"])
                 .action(ArgAction::Set)
                 .num_args(1),
         )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .requires("search")
+                .value_parser(value_parser!(usize))
+                .action(ArgAction::Set)
+                .help("maximum number of search results, 0 for no limit"),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .requires("search")
+                .value_parser(["relevance", "popularity", "votes", "name", "modified"])
+                .action(ArgAction::Set)
+                .help("sort search results by the given field"),
+        )
+        .arg(
+            Arg::new("by")
+                .long("by")
+                .requires("search")
+                .value_parser([
+                    "name",
+                    "name-desc",
+                    "maintainer",
+                    "keywords",
+                    "depends",
+                    "makedepends",
+                    "optdepends",
+                    "provides",
+                    "submitter",
+                    "comaintainers",
+                ])
+                .action(ArgAction::Set)
+                .help("package field to search by, defaults to name-desc"),
+        )
+        .arg(
+            Arg::new("regex")
+                .long("regex")
+                .requires("search")
+                .conflicts_with_all(["glob", "fuzzy"])
+                .action(ArgAction::SetTrue)
+                .help("interpret the search term as a regular expression"),
+        )
+        .arg(
+            Arg::new("glob")
+                .long("glob")
+                .requires("search")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("fuzzy")
+                .help("interpret the search term as a glob pattern"),
+        )
+        .arg(
+            Arg::new("fuzzy")
+                .long("fuzzy")
+                .requires("search")
+                .action(ArgAction::SetTrue)
+                .help("match package names and keywords tolerating typos"),
+        )
+        .arg(
+            Arg::new("no-menu")
+                .long("no-menu")
+                .requires("search")
+                .action(ArgAction::SetTrue)
+                .help("print search results without the installation menu"),
+        )
+        .arg(
+            Arg::new("reverse")
+                .long("reverse")
+                .requires("search")
+                .action(ArgAction::SetTrue)
+                .help("reverse the order of search results"),
+        )
         .arg(
             Arg::new("info")
                 .long("info")
-                .conflicts_with_all(&["search", "sysupgrade", "refresh"])
+                .conflicts_with_all(["search", "sysupgrade"])
                 .short('i')
                 .action(ArgAction::SetTrue)
                 .help("view package information"),
@@ -40,15 +126,22 @@ pub fn build_sync_command() -> Command {
             Arg::new("clear")
                 .long("clear")
                 .short('c')
-                .conflicts_with_all(&["search", "sysupgrade", "refresh"])
+                .conflicts_with_all(["search", "sysupgrade", "refresh"])
                 .action(ArgAction::SetTrue)
                 .help("clear package cache"),
         )
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .short('l')
+                .requires("clear")
+                .action(ArgAction::SetTrue)
+                .help("list cached packages instead of clearing them"),
+        )
         .arg(
             Arg::new("refresh")
                 .long("refresh")
                 .short('y')
-                .conflicts_with_all(&["info", "search"])
                 .action(ArgAction::SetTrue)
                 .help("download fresh package databases"),
         )
@@ -56,16 +149,15 @@ pub fn build_sync_command() -> Command {
             Arg::new("sysupgrade")
                 .long("sysupgrade")
                 .short('u')
-                .conflicts_with_all(&["info", "search"])
+                .conflicts_with_all(["info", "search"])
                 .action(ArgAction::SetTrue)
                 .help("upgrade all out-of-date packages"),
         )
         .arg(
             Arg::new("package")
                 .help("packages")
-                .required_unless_present_any(&["search", "clear", "refresh", "sysupgrade"])
+                .required_unless_present_any(["search", "clear", "refresh", "sysupgrade"])
                 .action(ArgAction::Set)
                 .num_args(1..),
         )
 }
-
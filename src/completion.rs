@@ -0,0 +1,126 @@
+use std::{fs::File, io::Write, path::Path};
+
+use memmap2::Mmap;
+
+use crate::config::expand_path;
+use crate::database::write_atomic;
+use crate::error::{Error, ErrorKind, Result};
+
+pub const COMPLETION_PATH: &str = "~/.cache/aurme/completion.idx";
+
+const MAGIC: &[u8; 8] = b"AURMEIDX";
+/// Magic and name count.
+const HEADER_LEN: usize = 8 + 4;
+
+/// Writes the package names sorted case insensitively, preceded by the offset of each name so
+/// that completion can binary search them without touching the package database.
+pub fn write_completion_index<'a>(path: &Path, names: impl Iterator<Item = &'a str>) -> Result<()> {
+    let mut names: Vec<(String, &str)> = names.map(|name| (name.to_lowercase(), name)).collect();
+    names.sort();
+
+    let mut offsets: Vec<u8> = Vec::with_capacity(names.len() * 4);
+    let mut blob: Vec<u8> = Vec::new();
+
+    for (_, name) in names.iter() {
+        offsets.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+        blob.extend_from_slice(name.as_bytes());
+    }
+
+    write_atomic(
+        path,
+        |file| {
+            file.write_all(MAGIC)?;
+            file.write_all(&(names.len() as u32).to_le_bytes())?;
+            file.write_all(&offsets)?;
+            file.write_all(&blob)
+        },
+        |_| Ok(()),
+    )
+}
+
+pub struct CompletionIndex {
+    mmap: Mmap,
+    len: usize,
+}
+
+impl CompletionIndex {
+    pub fn open() -> Result<CompletionIndex> {
+        let invalid = |message: &str| Error::new(ErrorKind::Database, message);
+
+        let file = File::open(expand_path(COMPLETION_PATH))
+            .map_err(|e| invalid("Failed to open the completion index").with_source(e))?;
+        // SAFETY: the index is only ever replaced as a whole by `aurme -Sy`, never modified in
+        // place while mapped.
+        let mmap = unsafe { Mmap::map(&file) }
+            .map_err(|e| invalid("Failed to map the completion index").with_source(e))?;
+
+        if mmap.len() < HEADER_LEN || &mmap[0..8] != MAGIC {
+            return Err(invalid("Invalid completion index"));
+        }
+
+        let len = u32::from_le_bytes(mmap[8..12].try_into().unwrap()) as usize;
+        if HEADER_LEN + len * 4 > mmap.len() {
+            return Err(invalid("Corrupted completion index"));
+        }
+
+        Ok(CompletionIndex { mmap, len })
+    }
+
+    /// Names starting with `prefix`, optionally ignoring case.
+    pub fn with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+        ignore_case: bool,
+    ) -> impl Iterator<Item = &'a str> {
+        let lowercase_prefix = prefix.to_lowercase();
+
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.name(mid).to_lowercase() < lowercase_prefix {
+                true => low = mid + 1,
+                false => high = mid,
+            }
+        }
+
+        (low..self.len)
+            .map(|i| self.name(i))
+            .take_while(move |name| name.to_lowercase().starts_with(&lowercase_prefix))
+            .filter(move |name| ignore_case || name.starts_with(prefix))
+    }
+
+    /// Names containing `word` anywhere, optionally ignoring case.
+    pub fn containing<'a>(
+        &'a self,
+        word: &'a str,
+        ignore_case: bool,
+    ) -> impl Iterator<Item = &'a str> {
+        let lowercase_word = word.to_lowercase();
+
+        (0..self.len)
+            .map(|i| self.name(i))
+            .filter(move |name| match ignore_case {
+                true => name.to_lowercase().contains(&lowercase_word),
+                false => name.contains(word),
+            })
+    }
+
+    fn name(&self, i: usize) -> &str {
+        let blob_offset = HEADER_LEN + self.len * 4;
+        let offset = |i: usize| {
+            let at = HEADER_LEN + i * 4;
+            u32::from_le_bytes(self.mmap[at..at + 4].try_into().unwrap()) as usize
+        };
+
+        let start = blob_offset + offset(i);
+        let end = match i + 1 < self.len {
+            true => blob_offset + offset(i + 1),
+            false => self.mmap.len(),
+        };
+
+        self.mmap
+            .get(start..end)
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .unwrap_or_default()
+    }
+}
@@ -1,41 +1,188 @@
 use home::home_dir;
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
-use crate::theme;
+use crate::observer::Warn;
+use crate::package::Package;
+use crate::runner::OutputMode;
 
 pub const CACHE_PATH: &str = "~/.cache/aurme";
 pub const PACKAGES_CACHE_PATH: &str = "~/.cache/aurme/packages";
 pub const CONFIG_PATH: &str = "~/.config/aurme/config.json";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub enum VerboseOtion {
+pub enum VerboseOption {
     Quiet,
     Default,
     Verbose,
 }
 
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[non_exhaustive]
+pub enum SortOption {
+    Relevance,
+    Popularity,
+    Votes,
+    Name,
+    Modified,
+}
+
+impl FromStr for SortOption {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<SortOption, String> {
+        match value {
+            "relevance" => Ok(SortOption::Relevance),
+            "popularity" => Ok(SortOption::Popularity),
+            "votes" => Ok(SortOption::Votes),
+            "name" => Ok(SortOption::Name),
+            "modified" => Ok(SortOption::Modified),
+            _ => Err(format!("Unknown sort option \"{}\"", value)),
+        }
+    }
+}
+
+/// Where package metadata comes from: the full metadata dump synced with `-Sy`, or the RPC
+/// interface queried for just the packages involved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[non_exhaustive]
+pub enum MetadataSource {
+    Dump,
+    Rpc,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub keep_cache: bool,
     pub no_confirm: bool,
-    pub verbose: VerboseOtion,
+    pub verbose: VerboseOption,
+    #[serde(default)]
+    pub skip_pgp_check: bool,
+    #[serde(default)]
+    pub keyserver: Option<String>,
+    #[serde(default = "default_search_limit")]
+    pub search_limit: usize,
+    #[serde(default = "default_search_sort")]
+    pub search_sort: SortOption,
+    /// Days after which the database is considered stale, 0 to never warn about it.
+    #[serde(default = "default_database_max_age")]
+    pub database_max_age: u64,
+    /// Refreshes a stale database before installing or upgrading instead of warning.
+    #[serde(default)]
+    pub auto_refresh: bool,
+    /// Base URL of the AUR, or a `file://` directory.
+    #[serde(default = "default_aur_url")]
+    pub aur_url: String,
+    /// Base URLs tried in order when `aur_url` fails.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    /// Path of the metadata dump relative to the base URL.
+    #[serde(default = "default_metadata_path")]
+    pub metadata_path: String,
+    /// Path of a package snapshot relative to the base URL, `{name}` and `{url_path}` are
+    /// replaced by the package name and the AUR snapshot path.
+    #[serde(default = "default_snapshot_path")]
+    pub snapshot_path: String,
+    #[serde(default = "default_metadata_source")]
+    pub metadata_source: MetadataSource,
+    /// Seconds to wait for a connection to be established.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Seconds to wait for data from an established connection.
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64,
+    /// Attempts after a transient network error, waiting twice as long each time.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Proxy for every request, e.g. `http://proxy:3128` or `socks5://proxy:1080`. The usual
+    /// proxy environment variables are still honored when unset.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// PEM file of additional certificate authorities to trust.
+    #[serde(default)]
+    pub ca_bundle: Option<String>,
+    /// Snapshots downloaded at the same time.
+    #[serde(default = "default_parallel_downloads")]
+    pub parallel_downloads: usize,
 }
 
-impl Config {
-    pub fn default() -> Config {
+fn default_search_limit() -> usize {
+    10
+}
+
+fn default_search_sort() -> SortOption {
+    SortOption::Relevance
+}
+
+fn default_database_max_age() -> u64 {
+    7
+}
+
+fn default_aur_url() -> String {
+    String::from("https://aur.archlinux.org")
+}
+
+fn default_metadata_path() -> String {
+    String::from("/packages-meta-ext-v1.json.gz")
+}
+
+fn default_snapshot_path() -> String {
+    String::from("{url_path}")
+}
+
+fn default_metadata_source() -> MetadataSource {
+    MetadataSource::Dump
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    30
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_parallel_downloads() -> usize {
+    4
+}
+
+impl Default for Config {
+    fn default() -> Config {
         Config {
             keep_cache: true,
             no_confirm: false,
-            verbose: VerboseOtion::Default,
+            verbose: VerboseOption::Default,
+            skip_pgp_check: false,
+            keyserver: None,
+            search_limit: default_search_limit(),
+            search_sort: default_search_sort(),
+            database_max_age: default_database_max_age(),
+            auto_refresh: false,
+            aur_url: default_aur_url(),
+            mirrors: Vec::new(),
+            metadata_path: default_metadata_path(),
+            snapshot_path: default_snapshot_path(),
+            metadata_source: default_metadata_source(),
+            connect_timeout: default_connect_timeout(),
+            read_timeout: default_read_timeout(),
+            retries: default_retries(),
+            proxy: None,
+            ca_bundle: None,
+            parallel_downloads: default_parallel_downloads(),
         }
     }
+}
 
+impl Config {
     fn create(config: &Config, config_path: &PathBuf) -> Result<(), std::io::Error> {
         let json = serde_json::to_string_pretty(&config)?;
-        if let Err(_) = std::fs::metadata(config_path) {
+        if std::fs::metadata(config_path).is_err() {
             std::fs::create_dir_all(
                 config_path
                     .parent()
@@ -46,7 +193,10 @@ impl Config {
         std::fs::write(config_path, json)
     }
 
-    pub fn read(path: &str) -> Result<Config, std::io::Error> {
+    /// Reads the config at `path`, creating the default one if there is none. A config that can't
+    /// be parsed is moved to `config.old` and replaced with the default, which is reported to
+    /// `warn`.
+    pub fn read(path: &str, warn: Warn) -> Result<Config, std::io::Error> {
         let config_path: PathBuf = expand_path(path);
 
         if !config_path.exists() {
@@ -55,35 +205,48 @@ impl Config {
             return Ok(default);
         }
 
-        let json = std::fs::read_to_string(config_path.to_owned())?;
+        let json = std::fs::read_to_string(&config_path)?;
         if let Ok(config) = serde_json::from_str::<Config>(&json) {
             return Ok(config);
         }
 
-        eprintln!(
-            "{}",
-            theme::colorize(
-                theme::Type::Warning,
-                "Couldn't read config file.
-                \nYour config file will be overwritten with the default config.
-                \nOld config file has been renamed to config.old.\n"
-            )
-        );
-
         std::fs::rename(&config_path, config_path.with_extension("old"))?;
         Config::create(&Config::default(), &config_path)?;
 
+        warn(
+            "Couldn't read the config file, it was renamed to config.old and replaced with the \
+             default config",
+        );
+
         Ok(Config::default())
     }
 
-    pub fn get_verbose_config(&self) -> (Stdio, Stdio) {
+    /// `aur_url` followed by the mirrors.
+    pub fn sources(&self) -> Vec<&str> {
+        std::iter::once(self.aur_url.as_str())
+            .chain(self.mirrors.iter().map(|mirror| mirror.as_str()))
+            .collect()
+    }
+
+    pub fn metadata_url(&self, source: &str) -> String {
+        format!("{}{}", source.trim_end_matches('/'), self.metadata_path)
+    }
+
+    pub fn snapshot_url(&self, source: &str, package: &Package) -> String {
+        let path = self
+            .snapshot_path
+            .replace("{name}", &package.name)
+            .replace("{url_path}", &package.url_path);
+
+        format!("{}{}", source.trim_end_matches('/'), path)
+    }
+
+    /// Where the stdout and stderr of makepkg and gpg go.
+    pub fn get_verbose_config(&self) -> (OutputMode, OutputMode) {
         match self.verbose {
-            VerboseOtion::Verbose => (
-                std::process::Stdio::inherit(),
-                std::process::Stdio::inherit(),
-            ),
-            VerboseOtion::Quiet => (std::process::Stdio::piped(), std::process::Stdio::piped()),
-            VerboseOtion::Default => (std::process::Stdio::piped(), std::process::Stdio::inherit()),
+            VerboseOption::Verbose => (OutputMode::Inherit, OutputMode::Inherit),
+            VerboseOption::Quiet => (OutputMode::Capture, OutputMode::Capture),
+            VerboseOption::Default => (OutputMode::Capture, OutputMode::Inherit),
         }
     }
 }
@@ -0,0 +1,173 @@
+use crate::{
+    database::get_local_packages,
+    error::Result,
+    package::{strip_version, Package},
+    runner::CommandRunner,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConflictReason {
+    Conflicts,
+    Replaces,
+}
+
+/// An installed package that would be removed by installing `package`.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub package: String,
+    pub installed: Package,
+    pub reason: ConflictReason,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Conflicts {
+    /// Pairs of packages of the plan that conflict with each other, which can't be installed.
+    pub within_plan: Vec<(String, String)>,
+    pub installed: Vec<Conflict>,
+}
+
+/// Checks the install plan for conflicts against the installed packages and between its own
+/// members.
+pub fn find_conflicts(packages: &[&Package], runner: &dyn CommandRunner) -> Result<Conflicts> {
+    // Nothing to check, without reading the whole local database
+    if packages.is_empty() {
+        return Ok(Conflicts::default());
+    }
+
+    let within_plan = get_plan_conflicts(packages)
+        .into_iter()
+        .map(|(a, b)| (a.name.to_owned(), b.name.to_owned()))
+        .collect();
+
+    let local_packages = get_local_packages(runner)?;
+    let installed = get_installed_conflicts(packages, &local_packages)
+        .into_iter()
+        .map(|(package, installed, reason)| Conflict {
+            package: package.name.to_owned(),
+            installed: installed.clone(),
+            reason,
+        })
+        .collect();
+
+    Ok(Conflicts {
+        within_plan,
+        installed,
+    })
+}
+
+fn get_plan_conflicts<'a>(packages: &[&'a Package]) -> Vec<(&'a Package, &'a Package)> {
+    let mut conflicts: Vec<(&Package, &Package)> = Vec::new();
+
+    for (i, a) in packages.iter().enumerate() {
+        for b in packages.iter().skip(i + 1) {
+            if conflicts_with(a, b) || conflicts_with(b, a) {
+                conflicts.push((a, b));
+            }
+        }
+    }
+
+    conflicts
+}
+
+fn get_installed_conflicts<'a, 'b>(
+    packages: &[&'a Package],
+    local_packages: &'b [Package],
+) -> Vec<(&'a Package, &'b Package, ConflictReason)> {
+    let mut conflicts: Vec<(&Package, &Package, ConflictReason)> = Vec::new();
+
+    for package in packages.iter() {
+        for installed in local_packages.iter() {
+            // upgrading a package in the plan is not a conflict
+            if packages.iter().any(|p| p.name == installed.name) {
+                continue;
+            }
+
+            if replaces(package, installed) {
+                conflicts.push((package, installed, ConflictReason::Replaces));
+            } else if conflicts_with(package, installed) || conflicts_with(installed, package) {
+                conflicts.push((package, installed, ConflictReason::Conflicts));
+            }
+        }
+    }
+
+    conflicts
+}
+
+fn conflicts_with(package: &Package, other: &Package) -> bool {
+    package
+        .conflicts
+        .iter()
+        .flatten()
+        .filter(|conflict| strip_version(conflict) != package.name)
+        .any(|conflict| other.satisfies(conflict))
+}
+
+fn replaces(package: &Package, other: &Package) -> bool {
+    package
+        .replaces
+        .iter()
+        .flatten()
+        .any(|replace| strip_version(replace) == other.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versioned(name: &str, version: &str) -> Package {
+        Package::new(
+            name.to_owned(),
+            None,
+            Some(version.to_owned()),
+            None,
+            None,
+            None,
+        )
+    }
+
+    fn conflicting(conflicts: &[&str]) -> Package {
+        Package {
+            conflicts: Some(conflicts.iter().map(|c| c.to_string()).collect()),
+            ..versioned("bar", "1.0-1")
+        }
+    }
+
+    #[test]
+    fn versioned_conflicts_only_match_their_versions() {
+        let package = conflicting(&["foo<2"]);
+
+        assert!(conflicts_with(&package, &versioned("foo", "1.5-1")));
+        assert!(!conflicts_with(&package, &versioned("foo", "3-1")));
+        assert!(conflicts_with(
+            &conflicting(&["foo"]),
+            &versioned("foo", "3-1")
+        ));
+    }
+
+    #[test]
+    fn versioned_conflicts_match_provided_versions() {
+        let package = conflicting(&["foo>=2"]);
+        let provider = |provide: &str| Package {
+            provides: Some(vec![provide.to_owned()]),
+            ..versioned("foo-git", "r10.abc-1")
+        };
+
+        assert!(conflicts_with(&package, &provider("foo=2.1")));
+        assert!(!conflicts_with(&package, &provider("foo=1.9")));
+        // Like pacman, a provide without a version doesn't meet a versioned entry
+        assert!(!conflicts_with(&package, &provider("foo")));
+        assert!(conflicts_with(&conflicting(&["foo"]), &provider("foo")));
+    }
+
+    #[test]
+    fn installed_conflicts_skip_versions_outside_the_constraint() {
+        let package = conflicting(&["foo<2", "baz"]);
+        let local_packages = [versioned("foo", "3-1"), versioned("baz", "1-1")];
+
+        let conflicts = get_installed_conflicts(&[&package], &local_packages);
+
+        let names: Vec<&str> = conflicts.iter().map(|(_, p, _)| p.name.as_str()).collect();
+        assert_eq!(names, ["baz"]);
+    }
+}
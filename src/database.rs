@@ -1,90 +1,500 @@
+use crate::cli::{download_bar, print_database_changes, print_status, print_warning};
+use crate::completion::{write_completion_index, COMPLETION_PATH};
 use crate::config::expand_path;
-use crate::install::AUR_URL;
-use crate::name_to_key;
+use crate::error::{Error, ErrorKind, Result};
 use flate2::read::GzDecoder;
+use memmap2::Mmap;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
 use std::{
-    collections::HashMap,
+    fmt,
     fs::File,
-    io::{Read, Write},
-    process::Command,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
 };
+use tokio::sync::mpsc::{channel, Receiver};
 
 use crate::{
-    config::CACHE_PATH,
+    config::{Config, CACHE_PATH},
+    fetch::{fetch_with_mirrors, Fetched, HttpClient, Progress, Validators},
+    observer::Warn,
     package::Package,
+    runner::{CommandRunner, Invocation, Output, OutputMode},
+    sync::{now, DatabaseChanges, SyncOutcome, SyncState},
     theme::{colorize, Type},
 };
 
-pub const DB_PATH: &str = "~/.cache/aurme/packages-meta.json";
+pub const DB_PATH: &str = "~/.cache/aurme/packages.db";
 
-pub async fn download_database() -> Result<HashMap<String, Vec<Package>>, Box<dyn std::error::Error>>
-{
-    let cache_path = expand_path(CACHE_PATH);
-    let db_path = expand_path(DB_PATH);
+const MAGIC: &[u8; 8] = b"AURMEDB\0";
+/// Must be bumped whenever `Package` changes, as records are not self describing.
+const FORMAT_VERSION: u32 = 2;
+/// Magic, format version, package count and the offsets of the names and the index.
+const HEADER_LEN: usize = 8 + 4 + 4 + 8 + 8;
+/// Name offset and length, record offset and length.
+const INDEX_ENTRY_LEN: usize = 8 + 4 + 8 + 4;
+/// Chunks of the response buffered between the download and the database writer.
+const CHANNEL_CAPACITY: usize = 16;
 
-    println!(
-        "{}",
-        colorize(Type::Info, "Synchronising package database...")
+pub async fn handle_refresh(config: &Config, json: bool) -> Result<()> {
+    print_status(
+        json,
+        &colorize(Type::Info, "Synchronising package database..."),
     );
 
-    if let Err(_) = std::fs::metadata(&cache_path) {
+    let bar = download_bar("packages database", None);
+    let progress = |downloaded, total: Option<u64>| {
+        if let Some(total) = total {
+            bar.set_length(total);
+        }
+        bar.set_position(downloaded);
+    };
+    let outcome = download_database(config, &progress, &print_warning).await;
+    bar.finish_and_clear();
+
+    match outcome? {
+        SyncOutcome::UpToDate => {
+            print_status(json, &colorize(Type::Success, "Database is up to date!"))
+        }
+        SyncOutcome::Updated { changes } => {
+            print_status(json, &colorize(Type::Success, "Database is updated!"));
+            if let Some(changes) = changes {
+                print_database_changes(&changes, json);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads the metadata dump and replaces the database with it, unless it didn't change since
+/// the last sync. `progress` is called with the downloaded and total bytes.
+pub async fn download_database(
+    config: &Config,
+    progress: Progress<'_>,
+    warn: Warn<'_>,
+) -> Result<SyncOutcome> {
+    let cache_path = expand_path(CACHE_PATH);
+    let db_path = expand_path(DB_PATH);
+
+    if std::fs::metadata(&cache_path).is_err() {
         std::fs::create_dir_all(&cache_path)?;
     }
 
-    let url = format!("{}/packages-meta-ext-v1.json.gz", AUR_URL);
-    let res = reqwest::get(&url).await?;
-    let content = res.bytes().await?;
+    let sources = config.sources();
+    let urls: Vec<String> = sources
+        .iter()
+        .map(|source| config.metadata_url(source))
+        .collect();
 
-    let mut decoder = GzDecoder::new(&content[..]);
-    let mut json_str = String::new();
-    decoder.read_to_string(&mut json_str)?;
+    let client = HttpClient::new(config, warn)?;
+    let sync_state = SyncState::read();
+    let validators = Validators {
+        etag: sync_state.etag.as_deref(),
+        last_modified: sync_state.last_modified.as_deref(),
+    };
+    // A database that can't be opened, e.g. one of an older format, must be downloaded again
+    let old_database = Database::open_path(&db_path).ok();
+    let validators_for = match old_database.is_some() {
+        true => sources
+            .iter()
+            .position(|source| sync_state.source.as_deref() == Some(*source)),
+        false => None,
+    };
 
-    let alphabet_map = parse_into_alphabet_map(&json_str);
-    let alphabet_json = serde_json::to_string(&alphabet_map)?;
+    let (source, fetched) = fetch_with_mirrors(&client, &urls, &validators, validators_for).await?;
 
-    let mut json_file = File::create(db_path)?;
-    json_file.write_all(alphabet_json.as_bytes())?;
-    println!("{}", colorize(Type::Success, "Database is updated!"));
+    let (mut body, etag, last_modified) = match fetched {
+        Fetched::NotModified => {
+            SyncState {
+                synced_at: Some(now()),
+                ..sync_state
+            }
+            .write()?;
+            return Ok(SyncOutcome::UpToDate);
+        }
+        Fetched::Content {
+            body,
+            etag,
+            last_modified,
+        } => (body, etag, last_modified),
+    };
 
-    Ok(alphabet_map)
-}
+    let new_sync_state = SyncState {
+        source: Some(sources[source].to_owned()),
+        etag,
+        last_modified,
+        synced_at: Some(now()),
+    };
 
-fn parse_into_alphabet_map(json_str: &String) -> HashMap<String, Vec<Package>> {
-    let packages: Vec<Package> = serde_json::from_str(&json_str)
-        .expect("Invalid json database, please fix or remove invalid file");
-    let mut alphabet_map: HashMap<String, Vec<Package>> = HashMap::new();
+    let old_count = old_database.as_ref().map(Database::package_count);
 
-    packages.iter().for_each(|package| {
-        alphabet_map
-            .entry(name_to_key(&package.name))
-            .or_insert(Vec::new())
-            .push(package.clone());
+    // The body is decompressed, parsed and written as it downloads, on a blocking thread fed
+    // through a bounded channel, so that neither the dump nor the packages are held in memory.
+    let (sender, receiver) = channel(CHANNEL_CAPACITY);
+    let writer = tokio::task::spawn_blocking(move || {
+        write_database(&db_path, ChannelReader::new(receiver), old_count)
     });
 
-    alphabet_map
+    let total = body.len();
+    let mut downloaded: u64 = 0;
+    let mut download_error: Option<Error> = None;
+    loop {
+        let chunk = match client.next_chunk(&mut body).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                // Makes the writer fail too, so that the truncated database isn't kept
+                let _ = sender.send(Err(e.to_string())).await;
+                download_error = Some(e);
+                break;
+            }
+        };
+        downloaded += chunk.len() as u64;
+        progress(downloaded, total);
+
+        // The writer stopped early if the channel is closed, its error is reported below
+        if sender.send(Ok(chunk)).await.is_err() {
+            break;
+        }
+    }
+    drop(sender);
+
+    let written = writer.await.map_err(|e| {
+        Error::new(ErrorKind::Other, "Failed to write the package database").with_source(e)
+    })?;
+    if let Some(e) = download_error {
+        return Err(e);
+    }
+    written?;
+
+    let new_database = Database::open()?;
+    write_completion_index(&expand_path(COMPLETION_PATH), new_database.names())?;
+    new_sync_state.write()?;
+
+    Ok(SyncOutcome::Updated {
+        changes: old_database
+            .map(|old_database| DatabaseChanges::compare(&old_database, &new_database)),
+    })
 }
 
-pub fn read_database() -> Result<HashMap<String, Vec<Package>>, Box<dyn std::error::Error>> {
-    let db_path = expand_path(DB_PATH);
+/// Reads the chunks sent by the download, or the error that interrupted it.
+struct ChannelReader {
+    receiver: Receiver<Result<Vec<u8>, String>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
 
-    let json: String = std::fs::read_to_string(db_path)?;
+impl ChannelReader {
+    fn new(receiver: Receiver<Result<Vec<u8>, String>>) -> ChannelReader {
+        ChannelReader {
+            receiver,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
 
-    Ok(serde_json::from_str(&json)?)
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(Ok(chunk)) => (self.chunk, self.position) = (chunk, 0),
+                Some(Err(e)) => return Err(io::Error::other(e)),
+                None => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
 }
 
-// temp
-pub fn get_installed_packages() -> Result<Vec<Package>, Box<dyn std::error::Error>> {
-    let installed_packages_output = Command::new("pacman")
-        .arg("-Qm")
-        .output()
-        .expect("Failed to get installed packages");
+/// Hands the packages of the JSON array to `on_package` one at a time as they are parsed.
+struct PackageVisitor<F>(F);
+
+impl<'de, F: FnMut(Package) -> io::Result<()>> Visitor<'de> for PackageVisitor<F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of packages")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(package) = seq.next_element::<Package>()? {
+            (self.0)(package).map_err(de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+/// Refuses to replace the database with an empty one, or one that lost more than half of the
+/// packages, which points to a truncated or bogus response rather than real AUR changes.
+fn validate_package_count(count: usize, old_count: Option<usize>) -> Result<()> {
+    let message = match old_count {
+        _ if count == 0 => "Received an empty package database, keeping the current one".to_owned(),
+        Some(old_count) if count < old_count / 2 => format!(
+            "Received {} packages where the current database has {}, keeping the current one",
+            count, old_count
+        ),
+        _ => return Ok(()),
+    };
+
+    Err(Error::new(ErrorKind::Database, message)
+        .with_hint("Try again later, or add mirrors to the config"))
+}
+
+/// Writes `path` through a temporary file that is synced to disk and renamed over it, so that an
+/// interrupted write never leaves a partial file behind. `validate` is run on the temporary file
+/// before it replaces the old one.
+pub fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+    validate: impl FnOnce(&Path) -> Result<()>,
+) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    let result = (|| -> Result<()> {
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        write(&mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        validate(&tmp_path)
+    })();
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
 
-    let installed_packages_str = std::str::from_utf8(&installed_packages_output.stdout)?;
+    std::fs::rename(&tmp_path, path)?;
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Writes the gzipped JSON array of packages read from `dump` as bincode records, in the order
+/// they come in, followed by their names and a fixed size index sorted by name so that lookups
+/// can binary search the memory mapped file. Only the names are kept in memory. The database is
+/// only replaced if it passes `validate_package_count` against `old_count`.
+fn write_database(path: &Path, dump: impl Read, old_count: Option<usize>) -> Result<()> {
+    let written = write_atomic(
+        path,
+        |file| {
+            file.write_all(&[0; HEADER_LEN])?;
+
+            let mut entries: Vec<(String, u64, u32)> = Vec::new();
+            let mut offset = HEADER_LEN as u64;
+
+            let mut deserializer =
+                serde_json::Deserializer::from_reader(BufReader::new(GzDecoder::new(dump)));
+            deserializer
+                .deserialize_seq(PackageVisitor(|package: Package| {
+                    let record =
+                        bincode::serde::encode_to_vec(&package, bincode::config::standard())
+                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    file.write_all(&record)?;
+                    entries.push((package.name, offset, record.len() as u32));
+                    offset += record.len() as u64;
+                    Ok(())
+                }))
+                .and_then(|_| deserializer.end())
+                .map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid package database received: {}", e),
+                    )
+                })?;
+
+            // Stable, so that the first of duplicated names is kept
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            entries.dedup_by(|a, b| a.0 == b.0);
+
+            let names_offset = offset;
+            let mut name_offset = 0u64;
+            for (name, _, _) in entries.iter() {
+                file.write_all(name.as_bytes())?;
+            }
+            let index_offset = names_offset + entries.iter().map(|e| e.0.len() as u64).sum::<u64>();
+
+            for (name, record_offset, record_len) in entries.iter() {
+                file.write_all(&name_offset.to_le_bytes())?;
+                file.write_all(&(name.len() as u32).to_le_bytes())?;
+                file.write_all(&record_offset.to_le_bytes())?;
+                file.write_all(&record_len.to_le_bytes())?;
+                name_offset += name.len() as u64;
+            }
+
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(MAGIC)?;
+            file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+            file.write_all(&(entries.len() as u32).to_le_bytes())?;
+            file.write_all(&names_offset.to_le_bytes())?;
+            file.write_all(&index_offset.to_le_bytes())
+        },
+        |tmp_path| {
+            let database = Database::open_path(tmp_path)?;
+            validate_package_count(database.package_count(), old_count)?;
+
+            let first = database.names().next().unwrap_or_default();
+            if database.get(first).is_none() {
+                return Err(Error::new(
+                    ErrorKind::Database,
+                    "Failed to verify the written package database",
+                ));
+            }
+            Ok(())
+        },
+    );
+
+    written.map_err(|e| match e.kind() {
+        ErrorKind::Other => {
+            Error::new(ErrorKind::Database, "Failed to write the package database").with_source(e)
+        }
+        _ => e,
+    })
+}
+
+/// Read only view of the memory mapped package database. Names are read in place, packages are
+/// only decoded when asked for.
+pub struct Database {
+    mmap: Mmap,
+    len: usize,
+    names_offset: usize,
+    index_offset: usize,
+}
+
+impl Database {
+    pub fn open() -> Result<Database> {
+        Database::open_path(&expand_path(DB_PATH))
+    }
+
+    fn open_path(path: &Path) -> Result<Database> {
+        let invalid = |message: &str| Error::new(ErrorKind::Database, message);
+
+        let file = File::open(path)
+            .map_err(|e| invalid("Failed to open the package database").with_source(e))?;
+        // SAFETY: the database is only ever replaced as a whole by `aurme -Sy`, never modified
+        // in place while mapped.
+        let mmap = unsafe { Mmap::map(&file) }
+            .map_err(|e| invalid("Failed to map the package database").with_source(e))?;
+
+        if mmap.len() < HEADER_LEN || &mmap[0..8] != MAGIC {
+            return Err(invalid("Invalid database file"));
+        }
+
+        let read_u32 = |at: usize| u32::from_le_bytes(mmap[at..at + 4].try_into().unwrap());
+        let read_u64 = |at: usize| u64::from_le_bytes(mmap[at..at + 8].try_into().unwrap());
+
+        if read_u32(8) != FORMAT_VERSION {
+            return Err(invalid("Outdated database format"));
+        }
+
+        let len = read_u32(12) as usize;
+        let names_offset = read_u64(16) as usize;
+        let index_offset = read_u64(24) as usize;
+
+        // The offsets come from the file, so they can't be trusted not to overflow
+        let index_end = len
+            .checked_mul(INDEX_ENTRY_LEN)
+            .and_then(|index_len| index_offset.checked_add(index_len));
+        if names_offset < HEADER_LEN
+            || names_offset > index_offset
+            || index_end.is_none_or(|index_end| index_end > mmap.len())
+        {
+            return Err(invalid("Corrupted database file"));
+        }
+
+        Ok(Database {
+            mmap,
+            len,
+            names_offset,
+            index_offset,
+        })
+    }
+
+    pub fn package_count(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the package named exactly `name`.
+    pub fn get(&self, name: &str) -> Option<Package> {
+        let i = self.search(name).ok()?;
+        self.package(i)
+    }
+
+    /// Names of all packages, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        (0..self.len).map(|i| self.name(i))
+    }
+
+    /// Decodes every package one by one.
+    pub fn packages(&self) -> impl Iterator<Item = Package> + '_ {
+        (0..self.len).filter_map(|i| self.package(i))
+    }
+
+    fn search(&self, name: &str) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.name(mid).cmp(name) {
+                std::cmp::Ordering::Equal => return Ok(mid),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        Err(low)
+    }
 
-    let package_lines: Vec<&str> = installed_packages_str.trim().split('\n').collect();
+    fn entry(&self, i: usize) -> (usize, usize, usize, usize) {
+        let at = self.index_offset + i * INDEX_ENTRY_LEN;
+        let entry = &self.mmap[at..at + INDEX_ENTRY_LEN];
 
-    let installed_packages: Vec<Package> = package_lines
-        .into_iter()
+        (
+            u64::from_le_bytes(entry[0..8].try_into().unwrap()) as usize,
+            u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize,
+            u64::from_le_bytes(entry[12..20].try_into().unwrap()) as usize,
+            u32::from_le_bytes(entry[20..24].try_into().unwrap()) as usize,
+        )
+    }
+
+    fn name(&self, i: usize) -> &str {
+        let (name_offset, name_len, _, _) = self.entry(i);
+        let start = self.names_offset.saturating_add(name_offset);
+
+        self.mmap
+            .get(start..start.saturating_add(name_len))
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .unwrap_or_default()
+    }
+
+    fn package(&self, i: usize) -> Option<Package> {
+        let (_, _, record_offset, record_len) = self.entry(i);
+        let record = self
+            .mmap
+            .get(record_offset..record_offset.checked_add(record_len)?)?;
+
+        bincode::serde::decode_from_slice(record, bincode::config::standard())
+            .ok()
+            .map(|(package, _)| package)
+    }
+}
+
+// temp
+pub fn get_installed_packages(runner: &dyn CommandRunner) -> Result<Vec<Package>> {
+    let output = run_pacman(runner, Invocation::new("pacman").arg("-Qm"))?;
+
+    // pacman exits with 1 and prints nothing when no foreign package is installed
+    if output.code == Some(1) && output.stdout.is_empty() && output.stderr.is_empty() {
+        return Ok(Vec::new());
+    }
+    let installed_packages_str = pacman_stdout(output)?;
+
+    let installed_packages: Vec<Package> = installed_packages_str
+        .lines()
+        .filter(|package_line| !package_line.trim().is_empty())
         .map(|package_line| {
             let mut package_parts = package_line.split_whitespace();
             let name = package_parts.next().unwrap_or("").to_owned();
@@ -96,3 +506,299 @@ pub fn get_installed_packages() -> Result<Vec<Package>, Box<dyn std::error::Erro
 
     Ok(installed_packages)
 }
+
+/// Reads every locally installed package (repo and foreign) together with what it provides,
+/// conflicts with and replaces.
+pub fn get_local_packages(runner: &dyn CommandRunner) -> Result<Vec<Package>> {
+    let output_str = pacman_stdout(run_pacman(
+        runner,
+        Invocation::new("pacman").arg("-Qi").env("LC_ALL", "C"),
+    )?)?;
+
+    Ok(output_str
+        .split("\n\n")
+        .filter_map(parse_local_package)
+        .collect())
+}
+
+fn run_pacman(runner: &dyn CommandRunner, invocation: Invocation) -> Result<Output> {
+    runner
+        .run(&invocation.output((OutputMode::Capture, OutputMode::Capture)))
+        .map_err(|e| Error::new(ErrorKind::Pacman, "Failed to run pacman").with_source(e))
+}
+
+/// The stdout of a successful query, any failure being reported with what pacman printed.
+fn pacman_stdout(output: Output) -> Result<String> {
+    if !output.is_success() {
+        let code = output
+            .code
+            .map_or_else(|| "a signal".to_owned(), |code| format!("code {}", code));
+        let error = Error::new(
+            ErrorKind::Pacman,
+            format!("Pacman query failed with {}", code),
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        return Err(match stderr.trim() {
+            "" => error,
+            stderr => error.with_hint(stderr.to_owned()),
+        });
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| {
+        Error::new(ErrorKind::Pacman, "Invalid output received from pacman").with_source(e)
+    })
+}
+
+fn parse_local_package(info: &str) -> Option<Package> {
+    let mut fields: Vec<(&str, String)> = Vec::new();
+
+    for line in info.lines() {
+        match line.split_once(" : ") {
+            Some((key, value)) if !line.starts_with(' ') => {
+                fields.push((key.trim(), value.trim().to_owned()))
+            }
+            _ => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            }
+        }
+    }
+
+    let get = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    let get_list = |key: &str| match get(key) {
+        None | Some("None") => None,
+        Some(value) => Some(value.split_whitespace().map(String::from).collect()),
+    };
+
+    let mut package = Package::new(
+        get("Name")?.to_owned(),
+        None,
+        get("Version").map(String::from),
+        None,
+        None,
+        None,
+    );
+    package.provides = get_list("Provides");
+    package.conflicts = get_list("Conflicts With");
+    package.replaces = get_list("Replaces");
+
+    Some(package)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::runner::RecordingRunner;
+
+    use super::*;
+
+    /// A path in a folder of its own, removed first if a previous run left it behind.
+    fn temp_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aurme-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("packages.db")
+    }
+
+    /// Gzipped metadata dump of packages named `names`, at version `1.0-<position>`.
+    fn dump(names: &[&str]) -> Vec<u8> {
+        let packages: Vec<String> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                format!(
+                    r#"{{"Name":"{}","Version":"1.0-{}","Popularity":1.5,"URLPath":"/cgit/{}.tar.gz"}}"#,
+                    name, i, name
+                )
+            })
+            .collect();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(format!("[{}]", packages.join(",")).as_bytes())
+            .unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn written_databases_are_read_back() {
+        let path = temp_path("read-back");
+
+        write_database(&path, &dump(&["yay", "paru", "aurme", "paru"])[..], None).unwrap();
+        let database = Database::open_path(&path).unwrap();
+
+        assert_eq!(database.package_count(), 3);
+        assert_eq!(
+            database.names().collect::<Vec<_>>(),
+            ["aurme", "paru", "yay"]
+        );
+        assert_eq!(database.get("yay").unwrap().version, "1.0-0");
+        // The first of duplicated names is kept
+        assert_eq!(database.get("paru").unwrap().version, "1.0-1");
+        assert!(database.get("pikaur").is_none());
+        assert_eq!(database.packages().count(), 3);
+        assert!(!path.with_extension("tmp").exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupted_headers_fail() {
+        let path = temp_path("corrupted");
+        write_database(&path, &dump(&["yay", "paru"])[..], None).unwrap();
+        let database = std::fs::read(&path).unwrap();
+
+        let corruptions: [(usize, u64); 3] = [
+            // Index offset overflowing once the index length is added
+            (24, u64::MAX - 9),
+            // Names offset inside the header
+            (16, 4),
+            // Index past the end of the file
+            (24, database.len() as u64),
+        ];
+        for (at, value) in corruptions {
+            let mut corrupted = database.clone();
+            corrupted[at..at + 8].copy_from_slice(&value.to_le_bytes());
+            std::fs::write(&path, corrupted).unwrap();
+
+            let error = Database::open_path(&path).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::Database);
+            assert_eq!(error.to_string(), "Corrupted database file");
+        }
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn databases_that_lost_most_packages_are_not_written() {
+        let path = temp_path("shrunk");
+        write_database(&path, &dump(&["yay", "paru", "aurme", "pikaur"])[..], None).unwrap();
+
+        let error = write_database(&path, &dump(&["yay"])[..], Some(4)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Database);
+
+        let error = write_database(&path, &dump(&[])[..], None).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Database);
+
+        // The current database is left as it was
+        assert_eq!(Database::open_path(&path).unwrap().package_count(), 4);
+        assert!(!path.with_extension("tmp").exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn package_counts_are_validated() {
+        assert!(validate_package_count(10, None).is_ok());
+        assert!(validate_package_count(5, Some(10)).is_ok());
+        assert!(validate_package_count(4, Some(10)).is_err());
+        assert!(validate_package_count(0, None).is_err());
+    }
+
+    #[test]
+    fn installed_packages_are_parsed() {
+        let runner = RecordingRunner::new();
+        runner.respond(
+            "pacman",
+            &["-Qm"],
+            Output::success("yay 12.3.5-1\nvscodium-bin 1.89.1-1\n\n"),
+        );
+
+        let packages = get_installed_packages(&runner).unwrap();
+
+        let packages: Vec<(&str, &str)> = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.as_str()))
+            .collect();
+        assert_eq!(
+            packages,
+            [("yay", "12.3.5-1"), ("vscodium-bin", "1.89.1-1")]
+        );
+        assert_eq!(runner.invocations()[0].args, ["-Qm"]);
+    }
+
+    #[test]
+    fn no_foreign_packages_is_not_an_error() {
+        let runner = RecordingRunner::new();
+        runner.respond("pacman", &["-Qm"], Output::failure(1));
+
+        assert!(get_installed_packages(&runner).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_pacman_queries_are_errors() {
+        let runner = RecordingRunner::new();
+        runner.respond(
+            "pacman",
+            &["-Qm"],
+            Output {
+                stderr: b"error: could not open database\n".to_vec(),
+                ..Output::failure(1)
+            },
+        );
+        runner.respond("pacman", &["-Qi"], Output::failure(1));
+
+        let err = get_installed_packages(&runner).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Pacman);
+        assert_eq!(err.hint(), Some("error: could not open database"));
+
+        let err = get_local_packages(&runner).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Pacman);
+    }
+
+    #[test]
+    fn local_packages_are_parsed() {
+        let runner = RecordingRunner::new();
+        runner.respond(
+            "pacman",
+            &["-Qi"],
+            Output::success(
+                "Name            : yay-bin\n\
+                 Version         : 12.3.5-1\n\
+                 Provides        : yay\n\
+                 Depends On      : pacman>6.1  git\n\
+                 Conflicts With  : yay  yay-git\n\
+                 Replaces        : None\n\
+                 \n\
+                 Name            : pipewire-jack\n\
+                 Version         : 1:1.0.5-1\n\
+                 Provides        : jack  libjack.so=0-64  libjacknet.so=0-64\n\
+                 \x20                 libjackserver.so=0-64\n\
+                 Conflicts With  : jack\n\
+                 Replaces        : None\n\n",
+            ),
+        );
+
+        let packages = get_local_packages(&runner).unwrap();
+
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "yay-bin");
+        assert_eq!(packages[0].version, "12.3.5-1");
+        assert_eq!(packages[0].provides, Some(vec!["yay".to_owned()]));
+        assert_eq!(
+            packages[0].conflicts,
+            Some(vec!["yay".to_owned(), "yay-git".to_owned()])
+        );
+        assert_eq!(packages[0].replaces, None);
+        assert_eq!(packages[1].version, "1:1.0.5-1");
+        assert_eq!(
+            packages[1].provides.as_deref().map(<[String]>::len),
+            Some(4)
+        );
+
+        let invocation = &runner.invocations()[0];
+        assert_eq!(invocation.args, ["-Qi"]);
+        assert_eq!(invocation.envs, [("LC_ALL".to_owned(), "C".to_owned())]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{error, fmt, io};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+type Source = Box<dyn error::Error + Send + Sync>;
+
+/// What failed, deciding the exit code and the default hint of an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Other,
+    InvalidArgument,
+    Config,
+    Network,
+    Database,
+    Download,
+    Extraction,
+    Dependency,
+    Build,
+    Pacman,
+    Aborted,
+    NotFound,
+}
+
+impl ErrorKind {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::InvalidArgument => 2,
+            ErrorKind::Config => 3,
+            ErrorKind::Network => 4,
+            ErrorKind::Database => 5,
+            ErrorKind::Download => 6,
+            ErrorKind::Extraction => 7,
+            ErrorKind::Dependency => 8,
+            ErrorKind::Build => 9,
+            ErrorKind::Pacman => 10,
+            ErrorKind::Aborted => 11,
+            ErrorKind::NotFound => 12,
+        }
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        match self {
+            ErrorKind::Config => Some("Check the config file at ~/.config/aurme/config.json"),
+            ErrorKind::Network | ErrorKind::Download => {
+                Some("Check your connection and proxy settings, or add mirrors to the config")
+            }
+            ErrorKind::Database => Some("Run aurme -Sy to refresh the package database"),
+            ErrorKind::Extraction => {
+                Some("Clear the cache of the package with aurme -Sc <package> and try again")
+            }
+            ErrorKind::Pacman => Some("Check that pacman works and its database isn't locked"),
+            ErrorKind::NotFound => Some("Search the AUR with aurme -Ss <term>"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+    hint: Option<String>,
+    source: Option<Source>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error {
+            kind,
+            message: message.into(),
+            hint: None,
+            source: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: impl Into<Source>) -> Error {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Replaces the default hint of the kind.
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Error {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref().or(self.kind.hint())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn error::Error + 'static))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        let kind = match e.kind() {
+            io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted => ErrorKind::Network,
+            _ => ErrorKind::Other,
+        };
+        Error::new(kind, "Input/output error").with_source(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        let url = match e.url() {
+            Some(url) => url.to_string(),
+            None => "the server".to_string(),
+        };
+
+        let (kind, message) = if e.is_builder() {
+            (
+                ErrorKind::InvalidArgument,
+                format!("Invalid request to {}", url),
+            )
+        } else if e.is_timeout() {
+            (ErrorKind::Network, format!("Request to {} timed out", url))
+        } else if e.is_connect() {
+            (ErrorKind::Network, format!("Failed to connect to {}", url))
+        } else if let Some(status) = e.status() {
+            (
+                ErrorKind::Network,
+                format!("{} replied with {}", url, status),
+            )
+        } else if e.is_body() || e.is_decode() {
+            (
+                ErrorKind::Download,
+                format!("Failed to read the response of {}", url),
+            )
+        } else {
+            (ErrorKind::Network, format!("Request to {} failed", url))
+        };
+
+        Error::new(kind, message).with_source(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        let message = match e.classify() {
+            serde_json::error::Category::Io => "Failed to write JSON",
+            serde_json::error::Category::Data => "Failed to convert to or from JSON",
+            _ => "Invalid JSON",
+        };
+        Error::new(ErrorKind::Other, message).with_source(e)
+    }
+}
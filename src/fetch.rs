@@ -0,0 +1,299 @@
+use std::{fs::File, io::Read, time::Duration};
+
+use reqwest::header::{
+    HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+};
+use reqwest::{Certificate, IntoUrl, Proxy, Response, StatusCode};
+
+use crate::config::{expand_path, Config};
+use crate::error::{Error, ErrorKind, Result};
+use crate::observer::Warn;
+
+const USER_AGENT: &str = concat!("aurme/", env!("CARGO_PKG_VERSION"));
+/// Longest wait asked by a `Retry-After` header that is honoured, so that a server can't stall a
+/// command for hours.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+pub enum Fetched {
+    NotModified,
+    Content {
+        body: Body,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+/// Body of a fetched url, read in chunks with `HttpClient::next_chunk` so that it never has to be
+/// held in memory as a whole.
+pub enum Body {
+    File { file: File, len: u64 },
+    Response(Response),
+}
+
+impl Body {
+    /// Size of the body, if known.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::File { len, .. } => Some(*len),
+            Body::Response(res) => res.content_length(),
+        }
+    }
+}
+
+impl From<Response> for Body {
+    fn from(res: Response) -> Body {
+        Body::Response(res)
+    }
+}
+
+/// Validators of a previous response, sent to only get the content again if it changed.
+#[derive(Default, Clone, Copy)]
+pub struct Validators<'a> {
+    pub etag: Option<&'a str>,
+    pub last_modified: Option<&'a str>,
+}
+
+/// Called with the bytes downloaded so far and the total size, if known.
+pub type Progress<'a> = &'a (dyn Fn(u64, Option<u64>) + Sync);
+
+/// HTTP client shared by every request, configured from the timeouts, retries, proxy and CA
+/// bundle of the config. Retries and mirror fallbacks are reported to `warn`.
+pub struct HttpClient<'a> {
+    client: reqwest::Client,
+    read_timeout: Duration,
+    retries: u32,
+    warn: Warn<'a>,
+}
+
+impl<'a> HttpClient<'a> {
+    pub fn new(config: &Config, warn: Warn<'a>) -> Result<HttpClient<'a>> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(Duration::from_secs(config.connect_timeout));
+
+        if let Some(proxy) = &config.proxy {
+            let proxy = Proxy::all(proxy).map_err(|e| {
+                Error::new(ErrorKind::Config, format!("Invalid proxy \"{}\"", proxy)).with_source(e)
+            })?;
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(ca_bundle) = &config.ca_bundle {
+            let path = expand_path(ca_bundle);
+            let invalid_bundle = |e: Box<dyn std::error::Error + Send + Sync>| {
+                Error::new(
+                    ErrorKind::Config,
+                    format!("Invalid CA bundle {}", path.display()),
+                )
+                .with_source(e)
+            };
+
+            let pem = std::fs::read_to_string(&path).map_err(|e| invalid_bundle(e.into()))?;
+            for certificate in split_pem_bundle(&pem) {
+                let certificate = Certificate::from_pem(certificate.as_bytes())
+                    .map_err(|e| invalid_bundle(e.into()))?;
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(HttpClient {
+            client: builder.build()?,
+            read_timeout: Duration::from_secs(config.read_timeout),
+            retries: config.retries,
+            warn,
+        })
+    }
+
+    /// Sends a GET request, retrying connection failures, timeouts, rate limiting and server
+    /// errors with exponential backoff. Waiting for the response fails after the read timeout.
+    pub async fn get(&self, url: impl IntoUrl, headers: HeaderMap) -> Result<Response> {
+        let url = url.into_url()?;
+        let mut attempt = 0;
+
+        loop {
+            let request = self.client.get(url.clone()).headers(headers.clone()).send();
+            let sent = tokio::time::timeout(self.read_timeout, request).await;
+
+            // `Some` for a failure worth retrying, along with the wait asked by the server
+            let (result, retry_after) = match sent {
+                Ok(Ok(res)) if is_transient_status(res.status()) => {
+                    let retry_after = res
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .map(Duration::from_secs);
+                    (Ok(res), Some(retry_after))
+                }
+                Ok(Ok(res)) => (Ok(res), None),
+                Ok(Err(e)) => {
+                    let transient = e.is_connect() || e.is_timeout() || e.is_request();
+                    (Err(Error::from(e)), transient.then_some(None))
+                }
+                Err(_) => (
+                    Err(Error::new(
+                        ErrorKind::Network,
+                        format!("Request to {} timed out", url),
+                    )),
+                    Some(None),
+                ),
+            };
+
+            let wait = match retry_after {
+                Some(wait) if attempt < self.retries => wait
+                    .map(|wait| wait.min(MAX_RETRY_AFTER))
+                    .unwrap_or(Duration::from_millis(500 * 2u64.pow(attempt))),
+                _ => return result,
+            };
+
+            let reason = match &result {
+                Ok(res) => format!("{} replied with {}", url, res.status()),
+                Err(e) => e.to_string(),
+            };
+            (self.warn)(&format!(
+                "{}. Retrying in {:.1}s...",
+                reason,
+                wait.as_secs_f32()
+            ));
+
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+
+    /// Reads the next chunk of `body`, failing if the server stops sending data for longer than
+    /// the read timeout. Returns `None` once the body has been read entirely.
+    pub async fn next_chunk(&self, body: &mut Body) -> Result<Option<Vec<u8>>> {
+        match body {
+            Body::File { file, .. } => {
+                let mut chunk = vec![0; 64 * 1024];
+                let read = file.read(&mut chunk)?;
+                chunk.truncate(read);
+                Ok((read > 0).then_some(chunk))
+            }
+            Body::Response(res) => {
+                let chunk = tokio::time::timeout(self.read_timeout, res.chunk())
+                    .await
+                    .map_err(|_| {
+                        Error::new(
+                            ErrorKind::Network,
+                            format!("Timed out reading {}", res.url()),
+                        )
+                    })??;
+                Ok(chunk.map(|chunk| chunk.to_vec()))
+            }
+        }
+    }
+
+    /// Reads the whole body. `progress` is called with the bytes read so far and the total, if
+    /// known.
+    pub async fn read_body(&self, mut body: Body, progress: Progress<'_>) -> Result<Vec<u8>> {
+        let total = body.len();
+        let mut bytes: Vec<u8> = Vec::new();
+
+        while let Some(chunk) = self.next_chunk(&mut body).await? {
+            bytes.extend_from_slice(&chunk);
+            progress(bytes.len() as u64, total);
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn split_pem_bundle(pem: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+
+    pem.split_inclusive(END)
+        .filter(|certificate| certificate.ends_with(END))
+        .map(|certificate| certificate.trim_start().to_string())
+        .collect()
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Fetches `url`, which can also be a local `file://` path.
+pub async fn fetch(
+    client: &HttpClient<'_>,
+    url: &str,
+    validators: &Validators<'_>,
+) -> Result<Fetched> {
+    if let Some(path) = url.strip_prefix("file://") {
+        let file = File::open(path).map_err(|e| {
+            Error::new(ErrorKind::Network, format!("Failed to open {}", path)).with_source(e)
+        })?;
+        let len = file.metadata()?.len();
+        return Ok(Fetched::Content {
+            body: Body::File { file, len },
+            etag: None,
+            last_modified: None,
+        });
+    }
+
+    // Validators that aren't valid header values are just not sent
+    let mut headers = HeaderMap::new();
+    if let Some(etag) = validators
+        .etag
+        .and_then(|etag| HeaderValue::from_str(etag).ok())
+    {
+        headers.insert(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = validators
+        .last_modified
+        .and_then(|last_modified| HeaderValue::from_str(last_modified).ok())
+    {
+        headers.insert(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let res = client.get(url, headers).await?;
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+
+    let res = res.error_for_status()?;
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(String::from)
+    };
+    let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+
+    Ok(Fetched::Content {
+        body: Body::Response(res),
+        etag,
+        last_modified,
+    })
+}
+
+/// Fetches the first of `urls` that responds successfully, returning its index along with the
+/// result. A failure while reading the body afterwards doesn't fall back to the next url.
+/// `validators` is only used for the url at `validators_for`.
+pub async fn fetch_with_mirrors(
+    client: &HttpClient<'_>,
+    urls: &[String],
+    validators: &Validators<'_>,
+    validators_for: Option<usize>,
+) -> Result<(usize, Fetched)> {
+    let mut last_error = Error::new(ErrorKind::Config, "No source configured");
+
+    for (i, url) in urls.iter().enumerate() {
+        let url_validators = match validators_for == Some(i) {
+            true => *validators,
+            false => Validators::default(),
+        };
+
+        match fetch(client, url, &url_validators).await {
+            Ok(fetched) => return Ok((i, fetched)),
+            Err(e) => {
+                if i + 1 < urls.len() {
+                    (client.warn)(&format!("{}. Trying next mirror...", e));
+                }
+                last_error = e;
+            }
+        }
+    }
+
+    Err(last_error)
+}
@@ -1,138 +1,352 @@
 use flate2::read::GzDecoder;
+use futures_util::{stream, StreamExt};
 use tar::Archive;
 
 use crate::build::build_packages;
 use crate::clean::remove_cache;
 use crate::cli::get_yes_no;
 use crate::cli::print_outdated_packages;
+use crate::cli::print_status;
+use crate::cli::print_warning;
+use crate::cli::CliObserver;
 use crate::config::expand_path;
 use crate::config::PACKAGES_CACHE_PATH;
-use crate::database::get_installed_packages;
-use crate::query::get_outdated_packages;
+use crate::conflicts::{find_conflicts, Conflicts};
+use crate::error::{Error, ErrorKind, Result};
+use crate::fetch::{fetch_with_mirrors, Fetched, HttpClient, Progress, Validators};
+use crate::json::{print_json, JsonOutdated};
+use crate::observer::{InstallEvent, InstallObserver, Warn};
+use crate::pgp::handle_pgp_keys;
+use crate::query::get_packages;
+use crate::query::get_similar_packages;
+use crate::query::get_upgrades;
+use crate::query::not_found_error;
+use crate::runner::CommandRunner;
+use crate::snapshot::{sha256, verify_snapshot, Snapshots};
 use std::fs::File;
-use std::{error::Error, io::Write};
+use std::io::Write;
 
 use crate::{
     config::Config,
-    database::read_database,
+    database::Database,
     package::Package,
-    query::query_exact_package,
     theme::{colorize, Type},
 };
 
-pub const AUR_URL: &str = "https://aur.archlinux.org";
+pub async fn handle_install(
+    packages: &[&str],
+    config: &Config,
+    runner: &dyn CommandRunner,
+) -> Result<()> {
+    let plan = plan_install(packages, config, runner, &print_warning).await?;
 
-pub async fn handle_install(packages: &Vec<&str>, config: &Config) -> Result<(), Box<dyn Error>> {
-    let packages_db = read_database()?;
+    if !plan.not_found.is_empty() {
+        println!("\nThe following packages do not exist in the AUR:");
+        // Suggestions need the local database, which may not exist with the RPC source
+        let packages_db = Database::open().ok();
+        plan.not_found.iter().for_each(|package| {
+            let similar = packages_db
+                .as_ref()
+                .map(|db| get_similar_packages(package, db, 3))
+                .unwrap_or_default();
+            match similar.is_empty() {
+                true => println!("  {}", package),
+                false => println!(
+                    "  {} (did you mean {}?)",
+                    package,
+                    colorize(Type::Info, &similar.join(", "))
+                ),
+            }
+        });
+        return Err(not_found_error(&plan.not_found));
+    }
 
-    let mut existent_packages: Vec<&Package> = Vec::new();
-    let mut non_existent_packages: Vec<&str> = Vec::new();
+    install(&plan, config, runner, &CliObserver::default()).await
+}
 
-    packages
-        .iter()
-        .for_each(|package| match query_exact_package(package, &packages_db) {
-            Some(x) => existent_packages.push(x),
-            None => non_existent_packages.push(package),
-        });
+pub async fn handle_sysupgrade(
+    packages: &[&str],
+    json: bool,
+    config: &Config,
+    runner: &dyn CommandRunner,
+) -> Result<()> {
+    let upgrades = get_upgrades(packages, config, runner, &print_warning).await?;
 
-    if non_existent_packages.len() > 0 {
-        println!(
-            "{} The following packages do not exist in the AUR:",
-            colorize(Type::Error, "\nError:")
+    if !upgrades.not_installed.is_empty() {
+        print_status(
+            json,
+            &format!(
+                "{} The following packages are not installed:",
+                colorize(Type::Warning, "Warning:")
+            ),
         );
-        non_existent_packages.iter().for_each(|package| {
-            println!("  {}", package);
+        upgrades.not_installed.iter().for_each(|package| {
+            print_status(json, &format!("  {}", package));
         });
+        print_status(json, "");
+    }
+
+    upgrades.not_in_aur.iter().for_each(|package| {
+        eprintln!(
+            "{}",
+            colorize(
+                Type::Warning,
+                &format!("Package {} no longer exists in AUR. Skipping...", package)
+            )
+        );
+    });
+
+    if json {
+        let json: Vec<JsonOutdated> = upgrades
+            .outdated
+            .iter()
+            .map(|upgrade| JsonOutdated {
+                name: &upgrade.installed.name,
+                local_version: &upgrade.installed.version,
+                aur_version: &upgrade.available.version,
+            })
+            .collect();
+        return print_json(&json);
+    }
+
+    if upgrades.outdated.is_empty() {
+        println!("{}", colorize(Type::Header, "System is up to date"));
         return Ok(());
     }
 
-    install_packages(&existent_packages, config).await
+    print_outdated_packages(&upgrades.outdated);
+
+    if !get_yes_no("Proceed with update?") {
+        return Err(Error::new(ErrorKind::Aborted, "Aborting..."));
+    }
+
+    let packages = upgrades
+        .outdated
+        .into_iter()
+        .map(|upgrade| upgrade.available)
+        .collect();
+
+    install_packages(packages, config, runner).await
 }
 
-pub async fn handle_sysupgrade(packages: &[&str], config: &Config) -> Result<(), Box<dyn Error>> {
-    let packages_db = read_database()?;
-    let mut installed_packages = get_installed_packages()?;
+/// Installs packages that are known to exist in the AUR, printing the progress.
+pub async fn install_packages(
+    packages: Vec<Package>,
+    config: &Config,
+    runner: &dyn CommandRunner,
+) -> Result<()> {
+    let plan = plan_packages(packages, runner)?;
+    install(&plan, config, runner, &CliObserver::default()).await
+}
 
-    if packages.len() > 0 {
-        let invalid = packages.iter().filter(|package| {
-            !installed_packages.iter().any(|x| x.name == **package)
-        }).collect::<Vec<&&str>>();
+/// Packages to install together, with what they would conflict with.
+#[derive(Debug, Clone)]
+pub struct InstallPlan {
+    pub packages: Vec<Package>,
+    /// Requested names that don't exist in the AUR, installing the plan fails unless empty.
+    pub not_found: Vec<String>,
+    pub conflicts: Conflicts,
+}
 
-        if invalid.len() > 0 {
-            println!(
-                "{} The following packages are not installed:",
-                colorize(Type::Warning, "Warning:")
-            );
-            invalid.iter().for_each(|package| {
-                println!("  {}", package);
-            });
-            println!();
-        }
+/// Looks `names` up and plans their install.
+pub async fn plan_install(
+    names: &[&str],
+    config: &Config,
+    runner: &dyn CommandRunner,
+    warn: Warn<'_>,
+) -> Result<InstallPlan> {
+    let (packages, not_found) = get_packages(names, config, warn).await?;
 
-        installed_packages.retain(|x| packages.contains(&x.name.as_str()));
-    }
+    let mut plan = plan_packages(packages, runner)?;
+    plan.not_found = not_found.into_iter().map(String::from).collect();
+    Ok(plan)
+}
 
-    let outdated: Vec<(&Package, &Package)> =
-        get_outdated_packages(&installed_packages, &packages_db);
+pub fn plan_packages(packages: Vec<Package>, runner: &dyn CommandRunner) -> Result<InstallPlan> {
+    let conflicts = find_conflicts(&packages.iter().collect::<Vec<&Package>>(), runner)?;
 
-    if outdated.len() == 0 {
-        println!("{}", colorize(Type::Header, "System is up to date"));
+    Ok(InstallPlan {
+        packages,
+        not_found: Vec::new(),
+        conflicts,
+    })
+}
+
+/// Downloads, builds and installs the packages of `plan`, reporting to `observer`.
+pub async fn install(
+    plan: &InstallPlan,
+    config: &Config,
+    runner: &dyn CommandRunner,
+    observer: &dyn InstallObserver,
+) -> Result<()> {
+    if !plan.not_found.is_empty() {
+        return Err(not_found_error(&plan.not_found));
+    }
+
+    if plan.packages.is_empty() {
         return Ok(());
     }
 
-    print_outdated_packages(&outdated);
+    check_conflicts(&plan.conflicts, config, observer)?;
 
-    if !get_yes_no("Proceed with update?") {
-        println!("{}", colorize(Type::Warning, "Aborting..."));
+    let packages: Vec<&Package> = plan.packages.iter().collect();
+    download_packages(&packages, config, observer).await?;
+    handle_pgp_keys(&packages, config, runner, observer)?;
+    build_packages(&packages, config, runner, observer)
+}
+
+/// Fails if the installation shouldn't proceed.
+fn check_conflicts(
+    conflicts: &Conflicts,
+    config: &Config,
+    observer: &dyn InstallObserver,
+) -> Result<()> {
+    if !conflicts.within_plan.is_empty() {
+        let pairs: Vec<String> = conflicts
+            .within_plan
+            .iter()
+            .map(|(a, b)| format!("{} and {}", a, b))
+            .collect();
+        return Err(Error::new(
+            ErrorKind::Dependency,
+            format!(
+                "Unresolvable package conflicts detected: {}",
+                pairs.join(", ")
+            ),
+        ));
+    }
+
+    if conflicts.installed.is_empty() {
         return Ok(());
     }
 
-    let packages = outdated.iter().map(|(_, db)| *db).collect();
+    if config.no_confirm {
+        return Err(
+            Error::new(ErrorKind::Dependency, "Installed packages would be removed")
+                .with_hint("Disable no_confirm to resolve"),
+        );
+    }
 
-    // TODO: check if there is the package in the cache
+    if !observer.confirm_conflicts(&conflicts.installed) {
+        return Err(Error::new(ErrorKind::Aborted, "Aborting..."));
+    }
 
-    install_packages(&packages, config).await
+    Ok(())
 }
 
-/// The argument `packages` is assumed to have already been validated for their existance on AUR
-pub async fn install_packages(
-    packages: &Vec<&Package>,
+/// Downloads the snapshots concurrently, at most `parallel_downloads` at a time, reusing the
+/// cached ones of the same version, which fail if they no longer match their recorded hash. On
+/// failure the remaining downloads are cancelled and the ones already downloaded are removed.
+async fn download_packages(
+    packages: &[&Package],
     config: &Config,
-) -> Result<(), Box<dyn std::error::Error>> {
-    download_packages(packages).await?;
-    build_packages(packages, config)
-}
+    observer: &dyn InstallObserver,
+) -> Result<()> {
+    let warn = |message: &str| observer.event(InstallEvent::Warning(message));
+    let client = HttpClient::new(config, &warn)?;
+    let mut snapshots = Snapshots::read();
+
+    observer.event(InstallEvent::DownloadsStarted {
+        count: packages.len(),
+    });
 
-async fn download_packages(packages: &Vec<&Package>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut successfully_downloaded: Vec<&Package> = Vec::new();
-    for package in packages {
-        match download(&package).await {
-            Ok(_) => {
-                successfully_downloaded.push(package);
-                eprintln!(
-                    "{} downloaded {}",
-                    colorize(Type::Success, "Successfully"),
-                    package.name
-                );
+    // Created up front, mapping the stream to them makes the install future not `Send`
+    let downloads: Vec<_> = packages
+        .iter()
+        .map(|package| {
+            let (client, snapshots) = (&client, &snapshots);
+            async move {
+                observer.event(InstallEvent::DownloadStarted { package });
+                let on_progress = |downloaded, total| {
+                    observer.event(InstallEvent::DownloadProgress {
+                        package,
+                        downloaded,
+                        total,
+                    })
+                };
+                let result = download(client, package, snapshots, config, &on_progress).await;
+                (*package, result)
+            }
+        })
+        .collect();
+    let mut downloads = stream::iter(downloads).buffer_unordered(config.parallel_downloads.max(1));
+
+    let mut successfully_downloaded: Vec<(&Package, String)> = Vec::new();
+    while let Some((package, result)) = downloads.next().await {
+        match result {
+            Ok(Some(sha256)) => {
+                successfully_downloaded.push((package, sha256));
+                observer.event(InstallEvent::Downloaded {
+                    package,
+                    cached: false,
+                });
             }
+            Ok(None) => observer.event(InstallEvent::Downloaded {
+                package,
+                cached: true,
+            }),
             Err(e) => {
-                eprintln!(
-                    "{} to download {}",
-                    colorize(Type::Error, "Failed"),
-                    package.name
-                );
-                remove_cache(successfully_downloaded)?;
+                observer.event(InstallEvent::DownloadFailed { package });
+                drop(downloads);
+                observer.event(InstallEvent::DownloadsFinished);
+                remove_cache(successfully_downloaded.iter().map(|(p, _)| *p).collect())?;
                 return Err(e);
             }
         }
     }
-    Ok(())
+    drop(downloads);
+    observer.event(InstallEvent::DownloadsFinished);
+
+    successfully_downloaded
+        .into_iter()
+        .for_each(|(package, sha256)| snapshots.record(package, sha256));
+    snapshots.write()
 }
 
-async fn download(package: &Package) -> Result<(), Box<dyn std::error::Error>> {
+/// Returns the hash of the downloaded snapshot, or `None` if the cached one was reused.
+async fn download(
+    client: &HttpClient<'_>,
+    package: &Package,
+    snapshots: &Snapshots,
+    config: &Config,
+    progress: Progress<'_>,
+) -> Result<Option<String>> {
     let cache_path = expand_path(PACKAGES_CACHE_PATH);
     let package_folder = cache_path.join(&package.name);
 
+    if snapshots
+        .get(&package.name)
+        .is_some_and(|record| record.version == package.version)
+    {
+        // Downloading it again would overwrite the changes made to it
+        verify_snapshot(&package.name, snapshots)?;
+        return Ok(None);
+    }
+
+    let urls: Vec<String> = config
+        .sources()
+        .iter()
+        .map(|source| config.snapshot_url(source, package))
+        .collect();
+    let failed_download = |e: Error| {
+        Error::new(
+            ErrorKind::Download,
+            format!("Failed to download the snapshot of \"{}\"", package.name),
+        )
+        .with_source(e)
+    };
+    let response = match fetch_with_mirrors(client, &urls, &Validators::default(), None)
+        .await
+        .map_err(failed_download)?
+    {
+        (_, Fetched::Content { body, .. }) => client
+            .read_body(body, progress)
+            .await
+            .map_err(failed_download)?,
+        (_, Fetched::NotModified) => unreachable!("no validators were sent"),
+    };
+
+    // Only touched once downloaded, so that cancelled downloads leave the cache as it was
     if !cache_path.exists() {
         std::fs::create_dir_all(&cache_path).expect("Failed to create cache directory");
     }
@@ -141,7 +355,6 @@ async fn download(package: &Package) -> Result<(), Box<dyn std::error::Error>> {
         std::fs::remove_dir_all(package_folder).expect("Failed to remove old package");
     }
 
-    let response = reqwest::get(package.get_url_path()).await?.bytes().await?;
     let file_path = cache_path.join(format!("{}.tar.gz", package.name));
 
     let decoder = GzDecoder::new(&response[..]);
@@ -151,7 +364,13 @@ async fn download(package: &Package) -> Result<(), Box<dyn std::error::Error>> {
 
     let mut archive = Archive::new(decoder);
 
-    archive.unpack(cache_path)?;
+    archive.unpack(cache_path).map_err(|e| {
+        Error::new(
+            ErrorKind::Extraction,
+            format!("Failed to extract the snapshot of \"{}\"", package.name),
+        )
+        .with_source(e)
+    })?;
 
-    Ok(())
+    Ok(Some(sha256(&response)))
 }
@@ -0,0 +1,81 @@
+use std::io::{stdout, Write};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::package::Package;
+
+/// Schema of the `--json` output, documented in the README. Fields may be added but never renamed
+/// or removed.
+#[derive(Serialize)]
+pub struct JsonPackage<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub description: Option<&'a str>,
+    pub maintainer: Option<&'a str>,
+    pub votes: u32,
+    pub popularity: f32,
+    pub last_modified: u64,
+    pub url: String,
+    pub keywords: &'a [String],
+    pub depends: &'a [String],
+    pub make_depends: &'a [String],
+    pub opt_depends: &'a [String],
+    pub provides: &'a [String],
+    pub conflicts: &'a [String],
+    pub replaces: &'a [String],
+    pub installed_version: Option<&'a str>,
+}
+
+impl<'a> JsonPackage<'a> {
+    pub fn new(
+        package: &'a Package,
+        installed_packages: &'a [Package],
+        config: &Config,
+    ) -> JsonPackage<'a> {
+        let list = |values: &'a Option<Vec<String>>| values.as_deref().unwrap_or(&[]);
+
+        JsonPackage {
+            name: &package.name,
+            version: &package.version,
+            description: package.description.as_deref(),
+            maintainer: package.maintainer.as_deref(),
+            votes: package.num_votes,
+            popularity: package.popularity,
+            last_modified: package.last_modified,
+            url: package.get_url_path(config),
+            keywords: list(&package.keywords),
+            depends: list(&package.depends),
+            make_depends: list(&package.make_depends),
+            opt_depends: list(&package.opt_depends),
+            provides: list(&package.provides),
+            conflicts: list(&package.conflicts),
+            replaces: list(&package.replaces),
+            installed_version: installed_packages
+                .iter()
+                .find(|p| p.name == package.name)
+                .map(|p| p.version.as_str()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct JsonOutdated<'a> {
+    pub name: &'a str,
+    pub local_version: &'a str,
+    pub aur_version: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct JsonCacheEntry {
+    pub name: String,
+    pub path: String,
+    pub snapshot: bool,
+    pub extracted: bool,
+}
+
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    writeln!(stdout(), "{}", serde_json::to_string_pretty(value)?)?;
+    Ok(())
+}
@@ -1,87 +1,198 @@
+//! AUR helper library behind the `aurme` command. [`Aurme`] syncs and searches the package
+//! database, computes upgrades and installs packages, reporting progress through callbacks and
+//! [`InstallObserver`] instead of printing.
+
 mod build;
 mod clean;
 mod cli;
+mod client;
+mod completion;
 mod config;
+mod conflicts;
 mod database;
+mod error;
+mod fetch;
 mod install;
+mod json;
+mod matcher;
+mod observer;
 mod package;
+mod pgp;
 mod query;
+mod rpc;
+mod runner;
+mod snapshot;
+mod sync;
 mod theme;
+mod version;
 
 use crate::clean::handle_clean;
-use crate::config::Config;
-use crate::config::CONFIG_PATH;
+use crate::clean::handle_list_cache;
+use crate::cli::print_warning;
 use crate::install::handle_install;
 use crate::install::handle_sysupgrade;
 use clap::ArgMatches;
-use database::download_database;
+use database::handle_refresh;
 use query::handle_info;
 use query::handle_search;
-use std::error::Error;
 
-pub const NON_ALPHA: &str = "non_alpha";
+pub use crate::client::Aurme;
+pub use crate::config::{Config, MetadataSource, SortOption, VerboseOption};
+pub use crate::conflicts::{Conflict, ConflictReason, Conflicts};
+pub use crate::error::{Error, ErrorKind, Result};
+pub use crate::install::InstallPlan;
+pub use crate::matcher::SearchMode;
+pub use crate::observer::{InstallEvent, InstallObserver};
+pub use crate::package::Package;
+pub use crate::pgp::PgpKey;
+pub use crate::query::{SearchField, SearchOptions, SearchResults, Upgrade, Upgrades};
+pub use crate::runner::{
+    CommandRunner, Invocation, Output, OutputMode, RecordingRunner, SystemRunner,
+};
+pub use crate::sync::{DatabaseChanges, SyncOutcome};
 
-pub async fn run(matches: ArgMatches) {
-    let result = match matches.subcommand() {
+/// Runs the command, `main` exits with the exit code of the error kind on failure.
+pub async fn run(matches: ArgMatches) -> Result<()> {
+    match matches.subcommand() {
         Some(("sync", sync_matches)) => {
-            let config = match Config::read(CONFIG_PATH) {
-                Ok(config) => config,
-                Err(e) => {
-                    eprintln!(
-                        "{}: {}",
-                        theme::colorize(theme::Type::Error, "Error getting config file"),
-                        e
-                    );
-                    return;
-                }
-            };
-            handle_sync(sync_matches, &config).await
+            let aurme = Aurme::from_config_file(print_warning)?;
+            handle_sync(sync_matches, &aurme).await
         }
         Some(("lookup", lookup_matches)) => query::handle_lookup(lookup_matches).await,
         _ => unreachable!(),
-    };
-
-    if let Err(e) = result {
-        eprintln!("{} {}", theme::colorize(theme::Type::Error, "Error:"), e);
     }
 }
 
-pub fn name_to_key(package_name: &str) -> String {
-    let first_char = package_name.chars().next().unwrap();
-    match first_char.is_alphabetic() {
-        true => first_char.to_string().to_lowercase(),
-        false => NON_ALPHA.to_string(),
-    }
-}
+async fn handle_sync(sync_matches: &ArgMatches, aurme: &Aurme) -> Result<()> {
+    let config = aurme.config();
 
-async fn handle_sync(sync_matches: &ArgMatches, config: &Config) -> Result<(), Box<dyn Error>> {
-    if sync_matches.contains_id("search") {
-        let search_term = sync_matches
-            .get_one::<String>("search")
-            .expect("impossible to get here");
-        return handle_search(search_term, &config).await;
+    if !sync_matches.get_flag("refresh") && !sync_matches.get_flag("clear") {
+        check_database_age(sync_matches, aurme).await?;
     }
 
     let packages: Vec<_> = sync_matches
         .get_many::<String>("package")
         .map(|vals| vals.map(|s| s.as_str()).collect())
-        .unwrap_or_else(Vec::new);
+        .unwrap_or_default();
+
+    let search = sync_matches.get_one::<String>("search").map(|search_term| {
+        (
+            search_term,
+            SearchOptions::from_matches(sync_matches, config),
+        )
+    });
 
-    if sync_matches.get_flag("refresh") {
-        download_database().await?;
+    if sync_matches.get_flag("refresh")
+        && needs_refresh(
+            sync_matches,
+            search.as_ref().map(|(_, options)| options),
+            &packages,
+            config,
+        )
+    {
+        handle_refresh(config, sync_matches.get_flag("json")).await?;
     };
 
+    if let Some((search_term, options)) = search {
+        return handle_search(
+            search_term,
+            &options,
+            sync_matches.get_flag("json"),
+            sync_matches.get_flag("no-menu"),
+            config,
+            aurme.runner(),
+        )
+        .await;
+    }
+
     if sync_matches.get_flag("sysupgrade") {
-        return Ok(handle_sysupgrade(&packages, &config).await?);
+        return handle_sysupgrade(
+            &packages,
+            sync_matches.get_flag("json"),
+            config,
+            aurme.runner(),
+        )
+        .await;
     };
 
     if sync_matches.get_flag("info") {
-        return handle_info(&packages, &config);
+        return handle_info(
+            &packages,
+            sync_matches.get_flag("json"),
+            config,
+            aurme.runner(),
+        )
+        .await;
     };
 
     if sync_matches.get_flag("clear") {
+        if sync_matches.get_flag("list") {
+            return handle_list_cache(sync_matches.get_flag("json"));
+        }
         return handle_clean(&packages);
     };
 
-    handle_install(&packages, &config).await
+    // -Sy alone only refreshes the database
+    if packages.is_empty() {
+        return Ok(());
+    }
+
+    handle_install(&packages, config, aurme.runner()).await
+}
+
+/// Whether `-y` has to sync the package database. The RPC source fetches fresh metadata for
+/// every command, so the database is then only synced on its own or for the searches that the
+/// RPC can't run, as it still backs those along with suggestions and completion.
+fn needs_refresh(
+    sync_matches: &ArgMatches,
+    search_options: Option<&SearchOptions>,
+    packages: &[&str],
+    config: &Config,
+) -> bool {
+    if config.metadata_source == MetadataSource::Dump {
+        return true;
+    }
+
+    match search_options {
+        Some(options) => !matches!(options.mode, SearchMode::Substring),
+        None => {
+            packages.is_empty()
+                && !sync_matches.get_flag("sysupgrade")
+                && !sync_matches.get_flag("info")
+        }
+    }
+}
+
+/// Warns about a stale database, or refreshes it before installing or upgrading when
+/// `auto_refresh` is set.
+async fn check_database_age(sync_matches: &ArgMatches, aurme: &Aurme) -> Result<()> {
+    let config = aurme.config();
+
+    // Fresh metadata is fetched for every command with the RPC source
+    if config.database_max_age == 0 || config.metadata_source == MetadataSource::Rpc {
+        return Ok(());
+    }
+
+    let age = match aurme.database_age() {
+        Some(age) => age,
+        None => return Ok(()),
+    };
+
+    let days = age.as_secs() / (24 * 60 * 60);
+    if days < config.database_max_age {
+        return Ok(());
+    }
+
+    let modifies_system = !sync_matches.contains_id("search") && !sync_matches.get_flag("info");
+    if config.auto_refresh && modifies_system {
+        return handle_refresh(config, sync_matches.get_flag("json")).await;
+    }
+
+    print_warning(&format!(
+        "Package database is {} days old. Try to {} to refresh it",
+        days,
+        theme::colorize(theme::Type::Info, "aurme -Sy"),
+    ));
+
+    Ok(())
 }
@@ -1,5 +1,6 @@
-use aurme::run;
-use clap::Command;
+use ansi_term::{Colour, Style};
+use aurme::{run, Error, ErrorKind};
+use clap::{Arg, ArgAction, Command};
 use commands::{build_lookup_command, build_sync_command};
 mod commands;
 
@@ -10,9 +11,39 @@ async fn main() {
         .version("0.0.1.1")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("print machine readable JSON output"),
+        )
         .subcommand(build_sync_command())
         .subcommand(build_lookup_command())
         .get_matches();
 
-    run(matches).await;
+    if let Err(e) = run(matches).await {
+        print_error(&e);
+        std::process::exit(e.kind().exit_code());
+    }
+}
+
+/// Prints `e` with its causes and hint. An abort is only a notice.
+fn print_error(e: &Error) {
+    if e.kind() == ErrorKind::Aborted {
+        println!("{}", Colour::Yellow.paint(e.to_string()));
+        return;
+    }
+
+    eprintln!("{} {}", Colour::Red.bold().paint("Error:"), e);
+
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        eprintln!("  {} {}", Style::new().bold().paint("caused by:"), cause);
+        source = cause.source();
+    }
+
+    if let Some(hint) = e.hint() {
+        eprintln!("  {} {}", Colour::Blue.bold().paint("hint:"), hint);
+    }
 }
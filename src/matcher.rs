@@ -0,0 +1,136 @@
+use glob::{MatchOptions, Pattern};
+use regex::{Regex, RegexBuilder};
+
+use crate::error::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum SearchMode {
+    Substring,
+    Regex,
+    Glob,
+    Fuzzy,
+}
+
+/// A search term compiled once for its mode. Matching is case insensitive.
+pub enum Matcher {
+    Substring(String),
+    Regex(Regex),
+    Glob(Pattern),
+    Fuzzy(String),
+}
+
+impl Matcher {
+    pub fn new(search_term: &str, mode: SearchMode) -> Result<Matcher> {
+        match mode {
+            SearchMode::Substring => Ok(Matcher::Substring(search_term.to_lowercase())),
+            SearchMode::Regex => RegexBuilder::new(search_term)
+                .case_insensitive(true)
+                .build()
+                .map(Matcher::Regex)
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidArgument,
+                        format!("Invalid regex \"{}\"", search_term),
+                    )
+                    .with_source(e)
+                }),
+            SearchMode::Glob => Pattern::new(search_term).map(Matcher::Glob).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidArgument,
+                    format!("Invalid glob \"{}\"", search_term),
+                )
+                .with_source(e)
+            }),
+            SearchMode::Fuzzy => Ok(Matcher::Fuzzy(search_term.to_lowercase())),
+        }
+    }
+
+    /// Whether `value` contains the term, matches the pattern, or is close enough to the term.
+    pub fn find(&self, value: &str) -> bool {
+        match self {
+            Matcher::Substring(term) => value.to_lowercase().contains(term),
+            Matcher::Regex(regex) => regex.is_match(value),
+            Matcher::Glob(pattern) => pattern.matches_with(
+                value,
+                MatchOptions {
+                    case_sensitive: false,
+                    ..MatchOptions::new()
+                },
+            ),
+            Matcher::Fuzzy(term) => fuzzy_score(term, value).is_some(),
+        }
+    }
+
+    /// Whether `value` is the term, or matches the pattern.
+    pub fn equals(&self, value: &str) -> bool {
+        match self {
+            Matcher::Substring(term) => value.to_lowercase() == *term,
+            _ => self.find(value),
+        }
+    }
+}
+
+/// Scores between 0 and 1 how close `value` is to the lowercase `term`, either as a subsequence
+/// favouring consecutive and word start matches, or within a small edit distance.
+/// Returns `None` if they are too far apart.
+pub fn fuzzy_score(term: &str, value: &str) -> Option<f32> {
+    let value = value.to_lowercase();
+    let term_len = term.chars().count();
+
+    if term_len == 0 {
+        return None;
+    }
+
+    let subsequence_score = get_subsequence_score(term, &value)
+        .map(|score| score / (3 * term_len) as f32)
+        .filter(|score| *score >= 0.5);
+
+    let max_distance = (term_len / 4).max(1);
+    let distance = strsim::osa_distance(term, &value);
+    let distance_score = match distance <= max_distance {
+        true => Some(1.0 - distance as f32 / term_len.max(value.chars().count()) as f32),
+        false => None,
+    };
+
+    match (subsequence_score, distance_score) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Each matched character is worth 1, plus 1 if it follows the previous match and 1 if it starts
+/// a word. Returns `None` if `term` isn't a subsequence of `value`.
+fn get_subsequence_score(term: &str, value: &str) -> Option<f32> {
+    let mut term_chars = term.chars().peekable();
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    let mut previous_matched = false;
+
+    for c in value.chars() {
+        let Some(&expected) = term_chars.peek() else {
+            break;
+        };
+
+        if c == expected {
+            term_chars.next();
+            score += 1;
+            if previous_matched {
+                score += 1;
+            }
+            if !previous.is_some_and(|p| p.is_alphanumeric()) {
+                score += 1;
+            }
+            previous_matched = true;
+        } else {
+            previous_matched = false;
+        }
+
+        previous = Some(c);
+    }
+
+    match term_chars.peek() {
+        Some(_) => None,
+        None => Some(score as f32),
+    }
+}
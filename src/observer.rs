@@ -0,0 +1,63 @@
+use crate::{conflicts::Conflict, package::Package, pgp::PgpKey};
+
+/// Called with the warnings of operations that carry on anyway, such as a retried request or a
+/// config file that had to be reset.
+pub type Warn<'a> = &'a (dyn Fn(&str) + Sync);
+
+/// Progress of an install, reported in the order it happens. Downloads run concurrently, so the
+/// events of different packages may interleave.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum InstallEvent<'a> {
+    DownloadsStarted {
+        count: usize,
+    },
+    DownloadStarted {
+        package: &'a Package,
+    },
+    DownloadProgress {
+        package: &'a Package,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// `cached` is set when the cached snapshot was reused instead.
+    Downloaded {
+        package: &'a Package,
+        cached: bool,
+    },
+    DownloadFailed {
+        package: &'a Package,
+    },
+    DownloadsFinished,
+    ImportingKeys {
+        count: usize,
+    },
+    KeysImported {
+        count: usize,
+    },
+    Building {
+        package: &'a Package,
+    },
+    Installed {
+        package: &'a Package,
+    },
+    Warning(&'a str),
+}
+
+/// Receives the progress of an install and answers the questions it asks. Every method has a
+/// default, which ignores events and declines.
+pub trait InstallObserver: Sync {
+    fn event(&self, _event: InstallEvent) {}
+
+    /// Whether to proceed when installed packages would be replaced. Not asked with
+    /// `no_confirm`, which fails instead.
+    fn confirm_conflicts(&self, _conflicts: &[Conflict]) -> bool {
+        false
+    }
+
+    /// Whether to import the missing PGP keys, the build may fail without them. Not asked with
+    /// `no_confirm`, which imports them.
+    fn confirm_key_import(&self, _keys: &[PgpKey]) -> bool {
+        false
+    }
+}
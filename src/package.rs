@@ -1,4 +1,5 @@
-use crate::install::AUR_URL;
+use crate::config::Config;
+use crate::version::meets_constraint;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -13,8 +14,32 @@ pub struct Package {
     pub popularity: f32,
     #[serde(rename = "URLPath")]
     pub url_path: String,
+    #[serde(rename = "OutOfDate", default)]
+    pub out_of_date: Option<u64>,
+    #[serde(rename = "Maintainer", default)]
+    pub maintainer: Option<String>,
+    #[serde(rename = "Submitter", default)]
+    pub submitter: Option<String>,
+    #[serde(rename = "CoMaintainers", default)]
+    pub co_maintainers: Option<Vec<String>>,
     #[serde(rename = "Depends", default)]
     pub depends: Option<Vec<String>>,
+    #[serde(rename = "MakeDepends", default)]
+    pub make_depends: Option<Vec<String>>,
+    #[serde(rename = "OptDepends", default)]
+    pub opt_depends: Option<Vec<String>>,
+    #[serde(rename = "Conflicts", default)]
+    pub conflicts: Option<Vec<String>>,
+    #[serde(rename = "Provides", default)]
+    pub provides: Option<Vec<String>>,
+    #[serde(rename = "Replaces", default)]
+    pub replaces: Option<Vec<String>>,
+    #[serde(rename = "Keywords", default)]
+    pub keywords: Option<Vec<String>>,
+    #[serde(rename = "NumVotes", default)]
+    pub num_votes: u32,
+    #[serde(rename = "LastModified", default)]
+    pub last_modified: u64,
 }
 
 impl Package {
@@ -36,23 +61,40 @@ impl Package {
                 Some(v) => v,
                 None => String::from("1"),
             },
-            popularity: match popularity {
-                Some(n) => n,
-                None => 0.0,
-            },
-            url_path: match url {
-                Some(u) => u,
-                None => String::from(""),
-            },
-            depends: match depends {
-                Some(d) => Some(d),
-                None => None,
-            },
+            popularity: popularity.unwrap_or(0.0),
+            url_path: url.unwrap_or_default(),
+            out_of_date: None,
+            maintainer: None,
+            submitter: None,
+            co_maintainers: None,
+            depends,
+            make_depends: None,
+            opt_depends: None,
+            conflicts: None,
+            provides: None,
+            replaces: None,
+            keywords: None,
+            num_votes: 0,
+            last_modified: 0,
         }
     }
 
-    pub fn get_url_path(&self) -> String {
-        AUR_URL.to_owned() + &self.url_path
+    pub fn get_url_path(&self, config: &Config) -> String {
+        config.snapshot_url(&config.aur_url, self)
+    }
+
+    /// Whether this package is, or provides, `dependency`, checking its version constraint as
+    /// pacman does: a provide without a version only satisfies unversioned dependencies.
+    pub fn satisfies(&self, dependency: &str) -> bool {
+        let name = strip_version(dependency);
+        let constraint = &dependency[name.len()..];
+
+        (self.name == name && meets_constraint(Some(&self.version), constraint))
+            || self.provides.iter().flatten().any(|provide| {
+                let provided = strip_version(provide);
+                let version = provide[provided.len()..].strip_prefix('=');
+                provided == name && meets_constraint(version, constraint)
+            })
     }
 
     pub fn get_description(&self) -> &str {
@@ -62,3 +104,11 @@ impl Package {
         }
     }
 }
+
+/// Strips the version constraint from a dependency string, e.g. `foo>=1.2` becomes `foo`.
+pub fn strip_version(dependency: &str) -> &str {
+    dependency
+        .split(['<', '>', '='])
+        .next()
+        .unwrap_or(dependency)
+}
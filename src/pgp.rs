@@ -0,0 +1,201 @@
+use std::path::Path;
+
+use crate::{
+    config::{expand_path, Config, PACKAGES_CACHE_PATH},
+    error::{Error, ErrorKind, Result},
+    observer::{InstallEvent, InstallObserver},
+    package::Package,
+    runner::{CommandRunner, Invocation, OutputMode},
+};
+
+const SRCINFO: &str = ".SRCINFO";
+
+/// A key of the `validpgpkeys` of `package` missing from the keyring.
+#[derive(Debug, Clone)]
+pub struct PgpKey {
+    pub package: String,
+    pub key: String,
+}
+
+/// Checks the `validpgpkeys` of every package against the user's keyring and offers to import
+/// the missing ones before building.
+pub fn handle_pgp_keys(
+    packages: &[&Package],
+    config: &Config,
+    runner: &dyn CommandRunner,
+    observer: &dyn InstallObserver,
+) -> Result<()> {
+    if config.skip_pgp_check {
+        return Ok(());
+    }
+
+    let cache_path = expand_path(PACKAGES_CACHE_PATH);
+
+    let mut keys: Vec<(&str, String)> = Vec::new();
+    for package in packages.iter() {
+        for key in read_valid_pgp_keys(&cache_path.join(&package.name))? {
+            if !keys.iter().any(|(_, k)| *k == key) {
+                keys.push((&package.name, key));
+            }
+        }
+    }
+
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    if !runner.exists("gpg") {
+        observer.event(InstallEvent::Warning(
+            "Required dependency \"gpg\" not found. Skipping PGP key import...",
+        ));
+        return Ok(());
+    }
+
+    let missing: Vec<PgpKey> = keys
+        .into_iter()
+        .filter(|(_, key)| !is_key_in_keyring(key, runner))
+        .map(|(package, key)| PgpKey {
+            package: package.to_owned(),
+            key,
+        })
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if !config.no_confirm && !observer.confirm_key_import(&missing) {
+        observer.event(InstallEvent::Warning("Skipping PGP key import..."));
+        return Ok(());
+    }
+
+    let keys: Vec<&str> = missing.iter().map(|key| key.key.as_str()).collect();
+    observer.event(InstallEvent::ImportingKeys { count: keys.len() });
+    import_keys(&keys, config, runner)?;
+    observer.event(InstallEvent::KeysImported { count: keys.len() });
+
+    Ok(())
+}
+
+fn read_valid_pgp_keys(package_path: &Path) -> Result<Vec<String>> {
+    let srcinfo_path = package_path.join(SRCINFO);
+
+    if !srcinfo_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let srcinfo = std::fs::read_to_string(srcinfo_path)?;
+
+    Ok(srcinfo
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .filter(|(field, _)| field.trim() == "validpgpkeys")
+        .map(|(_, key)| key.trim().to_owned())
+        .filter(|key| !key.is_empty())
+        .collect())
+}
+
+fn is_key_in_keyring(key: &str, runner: &dyn CommandRunner) -> bool {
+    let invocation = Invocation::new("gpg")
+        .arg("--list-keys")
+        .arg(key)
+        .output((OutputMode::Discard, OutputMode::Discard));
+
+    runner
+        .run(&invocation)
+        .map(|output| output.is_success())
+        .unwrap_or(false)
+}
+
+fn import_keys(keys: &[&str], config: &Config, runner: &dyn CommandRunner) -> Result<()> {
+    let mut invocation = Invocation::new("gpg");
+    if let Some(keyserver) = &config.keyserver {
+        invocation = invocation.arg("--keyserver").arg(keyserver);
+    }
+
+    let invocation = invocation
+        .arg("--recv-keys")
+        .args(keys.iter().copied())
+        .output(config.get_verbose_config());
+
+    let output = runner
+        .run(&invocation)
+        .map_err(|e| Error::new(ErrorKind::Dependency, "Failed to run gpg").with_source(e))?;
+
+    if !output.is_success() {
+        return Err(
+            Error::new(ErrorKind::Dependency, "Failed to import PGP keys")
+                .with_hint("Check above logs, or set another keyserver in the config"),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runner::{Output, RecordingRunner};
+
+    use super::*;
+
+    const KEY: &str = "F4A7678C9C6B0E62ECE3FE1D76FE8B1BB79FB2D3";
+
+    #[test]
+    fn keys_are_looked_up_in_the_keyring() {
+        let runner = RecordingRunner::new();
+        runner.respond("gpg", &["--list-keys"], Output::failure(2));
+        runner.respond("gpg", &["--list-keys", KEY], Output::success(""));
+
+        assert!(is_key_in_keyring(KEY, &runner));
+        assert!(!is_key_in_keyring("0123456789ABCDEF", &runner));
+
+        let invocation = &runner.invocations()[0];
+        assert_eq!(invocation.args, ["--list-keys", KEY]);
+        assert_eq!(
+            (invocation.stdout, invocation.stderr),
+            (OutputMode::Discard, OutputMode::Discard)
+        );
+    }
+
+    #[test]
+    fn keys_are_received_from_the_default_keyserver() {
+        let runner = RecordingRunner::new();
+
+        import_keys(&[KEY, "0123456789ABCDEF"], &Config::default(), &runner).unwrap();
+
+        let invocation = &runner.invocations()[0];
+        assert_eq!(invocation.program, "gpg");
+        assert_eq!(invocation.args, ["--recv-keys", KEY, "0123456789ABCDEF"]);
+    }
+
+    #[test]
+    fn keys_are_received_from_the_configured_keyserver() {
+        let runner = RecordingRunner::new();
+        let config = Config {
+            keyserver: Some("hkps://keyserver.ubuntu.com".to_owned()),
+            ..Config::default()
+        };
+
+        import_keys(&[KEY], &config, &runner).unwrap();
+
+        assert_eq!(
+            runner.invocations()[0].args,
+            [
+                "--keyserver",
+                "hkps://keyserver.ubuntu.com",
+                "--recv-keys",
+                KEY
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_imports_fail() {
+        let runner = RecordingRunner::new();
+        runner.respond("gpg", &["--recv-keys"], Output::failure(2));
+
+        let error = import_keys(&[KEY], &Config::default(), &runner).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Dependency);
+    }
+}
@@ -1,30 +1,189 @@
 use clap::ArgMatches;
 
-use crate::cli::{get_value_from_range, print_top_packages};
+use crate::cli::{
+    get_values_from_range, print_package_info, print_search_results, print_top_packages,
+    print_warning,
+};
+use crate::completion::CompletionIndex;
+use crate::error::{Error, ErrorKind, Result};
 use crate::install::install_packages;
-use crate::name_to_key;
-use crate::package::Package;
+use crate::json::{print_json, JsonPackage};
+use crate::matcher::{fuzzy_score, Matcher, SearchMode};
+use crate::observer::Warn;
+use crate::package::{strip_version, Package};
+use crate::rpc::RpcClient;
+use crate::runner::CommandRunner;
 use crate::theme::{colorize, Type};
-use std::collections::HashMap;
-use std::error::Error;
-use std::process::exit;
-
-use crate::{config::Config, database::read_database};
-
-pub async fn handle_search(search_term: &String, config: &Config) -> Result<(), Box<dyn Error>> {
-    let packages_db = match read_database() {
-        Ok(x) => x,
-        Err(_) => {
-            eprintln!(
-                "{} to read database. Try to {} to refresh the local database",
-                colorize(Type::Error, "Failed"),
-                colorize(Type::Info, "aurme -Sy"),
-            );
-            exit(1);
+use crate::version::vercmp;
+use std::io::{stdout, IsTerminal};
+use std::str::FromStr;
+
+use crate::{
+    config::{Config, MetadataSource, SortOption},
+    database::{get_installed_packages, Database},
+};
+
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum SearchField {
+    Name,
+    NameDesc,
+    Maintainer,
+    Keywords,
+    Depends,
+    MakeDepends,
+    OptDepends,
+    Provides,
+    Submitter,
+    CoMaintainers,
+}
+
+impl FromStr for SearchField {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<SearchField, String> {
+        match value {
+            "name" => Ok(SearchField::Name),
+            "name-desc" => Ok(SearchField::NameDesc),
+            "maintainer" => Ok(SearchField::Maintainer),
+            "keywords" => Ok(SearchField::Keywords),
+            "depends" => Ok(SearchField::Depends),
+            "makedepends" => Ok(SearchField::MakeDepends),
+            "optdepends" => Ok(SearchField::OptDepends),
+            "provides" => Ok(SearchField::Provides),
+            "submitter" => Ok(SearchField::Submitter),
+            "comaintainers" => Ok(SearchField::CoMaintainers),
+            _ => Err(format!("Unknown search field \"{}\"", value)),
         }
-    };
+    }
+}
+
+impl SearchField {
+    /// Name of the field, as used by `--by` and the RPC `by` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchField::Name => "name",
+            SearchField::NameDesc => "name-desc",
+            SearchField::Maintainer => "maintainer",
+            SearchField::Keywords => "keywords",
+            SearchField::Depends => "depends",
+            SearchField::MakeDepends => "makedepends",
+            SearchField::OptDepends => "optdepends",
+            SearchField::Provides => "provides",
+            SearchField::Submitter => "submitter",
+            SearchField::CoMaintainers => "comaintainers",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub by: SearchField,
+    pub mode: SearchMode,
+    /// Maximum number of results, 0 for no limit.
+    pub limit: usize,
+    pub sort: SortOption,
+    pub reverse: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResults {
+    pub packages: Vec<Package>,
+    /// Nothing matched the search term, `packages` are the ones closest to it instead.
+    pub similar: bool,
+}
+
+/// An installed package with a different version in the AUR.
+#[derive(Debug, Clone)]
+pub struct Upgrade {
+    pub installed: Package,
+    pub available: Package,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Upgrades {
+    pub outdated: Vec<Upgrade>,
+    /// Requested packages that aren't installed.
+    pub not_installed: Vec<String>,
+    /// Installed packages that no longer exist in the AUR.
+    pub not_in_aur: Vec<String>,
+}
+
+impl SearchOptions {
+    /// Searches by name and description, with the limit and sort of the config.
+    pub fn new(config: &Config) -> SearchOptions {
+        SearchOptions {
+            by: SearchField::NameDesc,
+            mode: SearchMode::Substring,
+            limit: config.search_limit,
+            sort: config.search_sort,
+            reverse: false,
+        }
+    }
+
+    pub(crate) fn from_matches(matches: &ArgMatches, config: &Config) -> SearchOptions {
+        SearchOptions {
+            by: matches
+                .get_one::<String>("by")
+                .and_then(|by| by.parse().ok())
+                .unwrap_or(SearchField::NameDesc),
+            mode: if matches.get_flag("regex") {
+                SearchMode::Regex
+            } else if matches.get_flag("glob") {
+                SearchMode::Glob
+            } else if matches.get_flag("fuzzy") {
+                SearchMode::Fuzzy
+            } else {
+                SearchMode::Substring
+            },
+            limit: matches
+                .get_one::<usize>("limit")
+                .copied()
+                .unwrap_or(config.search_limit),
+            sort: matches
+                .get_one::<String>("sort")
+                .and_then(|sort| sort.parse().ok())
+                .unwrap_or(config.search_sort),
+            reverse: matches.get_flag("reverse"),
+        }
+    }
+}
+
+pub async fn handle_search(
+    search_term: &str,
+    options: &SearchOptions,
+    json: bool,
+    no_menu: bool,
+    config: &Config,
+    runner: &dyn CommandRunner,
+) -> Result<()> {
+    let results = search(search_term, options, config, &print_warning).await?;
+    let top_packages = results.packages;
+
+    if results.similar {
+        eprintln!(
+            "{}",
+            colorize(
+                Type::Warning,
+                "No exact matches found, showing similar packages"
+            )
+        );
+    }
 
-    let top_packages = get_top_packages(&search_term, &packages_db);
+    let installed_packages = get_installed_packages(runner).unwrap_or_default();
+
+    if json {
+        let json: Vec<JsonPackage> = top_packages
+            .iter()
+            .map(|p| JsonPackage::new(p, &installed_packages, config))
+            .collect();
+        return print_json(&json);
+    }
+
+    if no_menu || !stdout().is_terminal() {
+        print_search_results(&top_packages, &installed_packages);
+        return Ok(());
+    }
 
     let len = top_packages.len();
     if len == 0 {
@@ -32,110 +191,415 @@ pub async fn handle_search(search_term: &String, config: &Config) -> Result<(),
         return Ok(());
     }
 
-    print_top_packages(&top_packages);
+    print_top_packages(&top_packages, &installed_packages);
 
-    if let Some(i) = get_value_from_range("Install package(s)", 1, len)? {
-        return install_packages(&vec![top_packages[i - 1]], config).await;
+    if let Some(selection) = get_values_from_range("Install package(s)", 1, len)? {
+        let selected_packages = selection
+            .iter()
+            .map(|i| top_packages[i - 1].clone())
+            .collect();
+        return install_packages(selected_packages, config, runner).await;
     }
 
     Ok(())
 }
 
-#[allow(dead_code, unused)]
-pub fn handle_info(packages: &[&str], config: &Config) -> Result<(), Box<dyn Error>> {
-    todo!()
+/// Searches the configured metadata source, returning the best matches first.
+pub async fn search(
+    search_term: &str,
+    options: &SearchOptions,
+    config: &Config,
+    warn: Warn<'_>,
+) -> Result<SearchResults> {
+    let matcher = Matcher::new(search_term, options.mode)?;
+
+    // The RPC only searches substrings, other modes still need the local database
+    match (config.metadata_source, options.mode) {
+        (MetadataSource::Rpc, SearchMode::Substring) => {
+            let packages = RpcClient::new(config, warn)?
+                .search(search_term, options.by)
+                .await?;
+            // Every result already matched on the server, even on fields it doesn't return
+            let packages = get_top_packages(packages.into_iter(), options, |package| {
+                get_match_score(&matcher, package, options.by)
+                    .or_else(|| Some(get_popularity_score(package)))
+            });
+            Ok(SearchResults {
+                packages,
+                similar: false,
+            })
+        }
+        _ => search_database(search_term, &matcher, options),
+    }
+}
+
+fn search_database(
+    search_term: &str,
+    matcher: &Matcher,
+    options: &SearchOptions,
+) -> Result<SearchResults> {
+    let packages_db = Database::open()?;
+
+    let top_packages = get_top_packages(packages_db.packages(), options, |package| {
+        get_match_score(matcher, package, options.by)
+    });
+
+    if !top_packages.is_empty() || !matches!(options.mode, SearchMode::Substring) {
+        return Ok(SearchResults {
+            packages: top_packages,
+            similar: false,
+        });
+    }
+
+    let matcher = Matcher::new(search_term, SearchMode::Fuzzy)?;
+    let similar_packages = get_top_packages(packages_db.packages(), options, |package| {
+        get_match_score(&matcher, package, options.by)
+    });
+
+    Ok(SearchResults {
+        similar: !similar_packages.is_empty(),
+        packages: similar_packages,
+    })
+}
+
+/// Looks `names` up in the configured metadata source, returning the packages found in the order
+/// of `names` and the names that weren't.
+pub async fn get_packages<'a>(
+    names: &[&'a str],
+    config: &Config,
+    warn: Warn<'_>,
+) -> Result<(Vec<Package>, Vec<&'a str>)> {
+    let mut found: Vec<Package> = match config.metadata_source {
+        MetadataSource::Dump => {
+            let packages_db = Database::open()?;
+            names
+                .iter()
+                .filter_map(|name| packages_db.get(name))
+                .collect()
+        }
+        MetadataSource::Rpc => RpcClient::new(config, warn)?.info(names).await?,
+    };
+
+    let mut existent_packages: Vec<Package> = Vec::new();
+    let mut non_existent_packages: Vec<&str> = Vec::new();
+
+    names
+        .iter()
+        .for_each(|name| match found.iter().position(|p| p.name == *name) {
+            Some(i) => existent_packages.push(found.swap_remove(i)),
+            None => non_existent_packages.push(name),
+        });
+
+    Ok((existent_packages, non_existent_packages))
+}
+
+pub async fn handle_info(
+    packages: &[&str],
+    json: bool,
+    config: &Config,
+    runner: &dyn CommandRunner,
+) -> Result<()> {
+    let (existent_packages, non_existent_packages) =
+        get_packages(packages, config, &print_warning).await?;
+
+    let installed_packages = get_installed_packages(runner).unwrap_or_default();
+
+    if json {
+        let json: Vec<JsonPackage> = existent_packages
+            .iter()
+            .map(|p| JsonPackage::new(p, &installed_packages, config))
+            .collect();
+        print_json(&json)?;
+    } else {
+        existent_packages
+            .iter()
+            .for_each(|package| print_package_info(package, &installed_packages, config));
+    }
+
+    match non_existent_packages.is_empty() {
+        true => Ok(()),
+        false => Err(not_found_error(&non_existent_packages)),
+    }
+}
+
+pub fn not_found_error(packages: &[impl AsRef<str>]) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!(
+            "Packages not found in the AUR: {}",
+            packages
+                .iter()
+                .map(|package| format!("\"{}\"", package.as_ref()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    )
 }
 
-pub async fn handle_lookup(lookup_matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+pub async fn handle_lookup(lookup_matches: &ArgMatches) -> Result<()> {
     let search_term = lookup_matches
         .get_one::<String>("word")
         .expect("impossible to reach");
 
-    let packages = read_database()?;
+    let ignore_case = lookup_matches.get_flag("ignore-case");
 
-    print_packages_matching(search_term, packages);
+    let index = CompletionIndex::open()?;
+
+    match lookup_matches.get_flag("substring") {
+        true => index
+            .containing(search_term, ignore_case)
+            .for_each(|name| println!("{}", name)),
+        false => index
+            .with_prefix(search_term, ignore_case)
+            .for_each(|name| println!("{}", name)),
+    }
 
     Ok(())
 }
 
-fn get_top_packages<'a>(
-    package_name: &str,
-    packages_db: &'a HashMap<String, Vec<Package>>,
-) -> Vec<&'a Package> {
-    let mut top_packages: Vec<&Package> = packages_db
-        .iter()
-        .flat_map(|(_, packages)| packages.iter())
-        .filter(|package| {
-            package
-                .name
-                .to_lowercase()
-                .contains(&package_name.to_lowercase())
-                || package
-                    .get_description()
-                    .to_lowercase()
-                    .contains(&package_name.to_lowercase())
+fn get_top_packages(
+    packages: impl Iterator<Item = Package>,
+    options: &SearchOptions,
+    score: impl Fn(&Package) -> Option<f32>,
+) -> Vec<Package> {
+    let mut top_packages: Vec<(Package, f32)> = packages
+        .filter_map(|package| {
+            let score = score(&package)?;
+            Some((package, score))
         })
-        .map(|package| package)
         .collect();
 
-    if top_packages.is_empty() {
-        return top_packages;
+    top_packages.sort_by(|(a, a_score), (b, b_score)| match options.sort {
+        SortOption::Relevance => b_score.total_cmp(a_score),
+        SortOption::Popularity => b.popularity.total_cmp(&a.popularity),
+        SortOption::Votes => b.num_votes.cmp(&a.num_votes),
+        SortOption::Name => a.name.cmp(&b.name),
+        SortOption::Modified => b.last_modified.cmp(&a.last_modified),
+    });
+
+    if options.reverse {
+        top_packages.reverse();
+    }
+
+    if options.limit > 0 {
+        top_packages.truncate(options.limit);
     }
 
-    top_packages.sort_by(|a, b| b.popularity.partial_cmp(&a.popularity).unwrap());
-    top_packages.truncate(10);
     top_packages
+        .into_iter()
+        .map(|(package, _)| package)
+        .collect()
 }
 
-fn print_packages_matching(search_term: &String, packages: HashMap<String, Vec<Package>>) {
-    if let Some(packages) = packages.get(&name_to_key(search_term)) {
-        packages.iter().for_each(|package| {
-            if package.name.starts_with(search_term) {
-                println!("{}", package.name);
-            }
-        });
+/// Scores how well `package` matches on the `by` field, weighted by its popularity and votes.
+/// Returns `None` if it doesn't match at all.
+fn get_match_score(matcher: &Matcher, package: &Package, by: SearchField) -> Option<f32> {
+    let score = match by {
+        SearchField::Name => get_name_score(matcher, package),
+        SearchField::NameDesc => {
+            get_name_score(matcher, package) + get_description_score(matcher, package)
+        }
+        field => match get_field_values(package, field)
+            .iter()
+            .any(|value| matcher.equals(value))
+        {
+            true => 10.0,
+            false => 0.0,
+        },
+    };
+
+    if score == 0.0 {
+        return None;
     }
+
+    Some(score + get_popularity_score(package))
+}
+
+fn get_popularity_score(package: &Package) -> f32 {
+    5.0 * package.popularity.ln_1p() + 2.0 * (package.num_votes as f32).ln_1p()
 }
 
-pub fn query_exact_package<'a>(
+fn get_name_score(matcher: &Matcher, package: &Package) -> f32 {
+    let search_term = match matcher {
+        Matcher::Substring(search_term) => search_term,
+        Matcher::Fuzzy(search_term) => {
+            return fuzzy_score(search_term, &package.name).map_or(0.0, |score| 30.0 * score)
+        }
+        _ => match matcher.find(&package.name) {
+            true => return 20.0,
+            false => return 0.0,
+        },
+    };
+    let name = package.name.to_lowercase();
+
+    if name == *search_term {
+        100.0
+    } else if name.starts_with(search_term) {
+        60.0
+    } else if name
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(search_term))
+    {
+        40.0
+    } else if name.contains(search_term) {
+        20.0
+    } else {
+        0.0
+    }
+}
+
+fn get_description_score(matcher: &Matcher, package: &Package) -> f32 {
+    let keyword_score = match package
+        .keywords
+        .iter()
+        .flatten()
+        .any(|keyword| matcher.equals(keyword))
+    {
+        true => 15.0,
+        false => 0.0,
+    };
+
+    let description_score =
+        match !matches!(matcher, Matcher::Fuzzy(_)) && matcher.find(package.get_description()) {
+            true => 5.0,
+            false => 0.0,
+        };
+
+    keyword_score + description_score
+}
+
+/// Values of a list or maintainer field, with version constraints and optdepends descriptions
+/// stripped.
+fn get_field_values(package: &Package, field: SearchField) -> Vec<&str> {
+    let values = match field {
+        SearchField::Maintainer => return package.maintainer.iter().map(|m| m.as_str()).collect(),
+        SearchField::Submitter => return package.submitter.iter().map(|s| s.as_str()).collect(),
+        SearchField::Keywords => &package.keywords,
+        SearchField::Depends => &package.depends,
+        SearchField::MakeDepends => &package.make_depends,
+        SearchField::OptDepends => &package.opt_depends,
+        SearchField::Provides => &package.provides,
+        SearchField::CoMaintainers => &package.co_maintainers,
+        SearchField::Name | SearchField::NameDesc => return vec![package.name.as_str()],
+    };
+
+    values
+        .iter()
+        .flatten()
+        .map(|value| strip_version(value.split(':').next().unwrap_or(value)).trim())
+        .collect()
+}
+
+/// Names of the packages closest to `package`, best first.
+pub fn get_similar_packages<'a>(
     package: &str,
-    database: &'a HashMap<String, Vec<Package>>,
-) -> Option<&'a Package> {
-    if let Some(packages) = database.get(&name_to_key(package)) {
-        return packages.iter().find(|p| p.name == *package);
+    database: &'a Database,
+    limit: usize,
+) -> Vec<&'a str> {
+    let package = package.to_lowercase();
+
+    let mut similar: Vec<(&str, f32)> = database
+        .names()
+        .filter_map(|name| Some((name, fuzzy_score(&package, name)?)))
+        .collect();
+
+    similar.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    similar.truncate(limit);
+    similar.into_iter().map(|(name, _)| name).collect()
+}
+
+/// Looks up the AUR version of the installed foreign packages named `names`, or of all of them
+/// if `names` is empty.
+pub async fn get_upgrades(
+    names: &[&str],
+    config: &Config,
+    runner: &dyn CommandRunner,
+    warn: Warn<'_>,
+) -> Result<Upgrades> {
+    let mut installed_packages = get_installed_packages(runner)?;
+
+    let not_installed = names
+        .iter()
+        .filter(|name| !installed_packages.iter().any(|p| p.name == **name))
+        .map(|name| name.to_string())
+        .collect();
+
+    if !names.is_empty() {
+        installed_packages.retain(|p| names.contains(&p.name.as_str()));
     }
-    None
+
+    let installed_names: Vec<&str> = installed_packages.iter().map(|p| p.name.as_str()).collect();
+    let (aur_packages, _) = get_packages(&installed_names, config, warn).await?;
+
+    let mut upgrades = get_outdated_packages(installed_packages, aur_packages);
+    upgrades.not_installed = not_installed;
+    Ok(upgrades)
 }
 
-pub fn get_outdated_packages<'a, 'b>(
-    installed_packages: &'a Vec<Package>,
-    database: &'b HashMap<String, Vec<Package>>,
-) -> Vec<(&'a Package, &'b Package)> {
-    let mut outdated: Vec<(&Package, &Package)> = Vec::new();
+/// Pairs the installed packages with their newer AUR version out of `aur_packages`.
+fn get_outdated_packages(installed_packages: Vec<Package>, aur_packages: Vec<Package>) -> Upgrades {
+    let mut upgrades = Upgrades::default();
 
-    installed_packages.iter().for_each(|package| {
-        let db_package = match query_exact_package(&package.name, database) {
+    installed_packages.into_iter().for_each(|package| {
+        let aur_package = match aur_packages.iter().find(|p| p.name == package.name) {
             Some(x) => x,
             None => {
+                // Debug packages are split from AUR packages when building, never uploaded
                 if !package.name.ends_with("debug") {
-                    eprintln!(
-                        "{}",
-                        colorize(
-                            Type::Warning,
-                            &format!(
-                                "Package {} no longer exists in AUR. Skipping...",
-                                package.name
-                            )
-                        )
-                    );
+                    upgrades.not_in_aur.push(package.name);
                 }
                 return;
             }
         };
-        if db_package.version != package.version {
-            outdated.push((package, db_package));
+        if vercmp(&aur_package.version, &package.version).is_gt() {
+            upgrades.outdated.push(Upgrade {
+                installed: package,
+                available: aur_package.clone(),
+            });
         }
     });
 
-    outdated
+    upgrades
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, version: &str) -> Package {
+        Package::new(
+            name.to_owned(),
+            None,
+            Some(version.to_owned()),
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn only_newer_aur_versions_are_outdated() {
+        let installed = vec![
+            package("yay", "12.3.4-1"),
+            package("paru", "1:2.0-1"),
+            package("foo-git", "r120.abc-1"),
+            package("bar", "1.0-1"),
+            package("bar-debug", "1.0-1"),
+        ];
+        let aur = vec![
+            package("yay", "12.3.10-1"),
+            package("paru", "2.1-1"),
+            package("foo-git", "r99.def-1"),
+        ];
+
+        let upgrades = get_outdated_packages(installed, aur);
+
+        let outdated: Vec<&str> = upgrades
+            .outdated
+            .iter()
+            .map(|upgrade| upgrade.installed.name.as_str())
+            .collect();
+        assert_eq!(outdated, ["yay"]);
+        assert_eq!(upgrades.not_in_aur, ["bar"]);
+    }
 }
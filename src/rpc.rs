@@ -0,0 +1,273 @@
+use reqwest::{header::HeaderMap, Url};
+use serde::Deserialize;
+
+use crate::{
+    config::Config,
+    error::{Error, ErrorKind, Result},
+    fetch::HttpClient,
+    observer::Warn,
+    package::Package,
+    query::SearchField,
+};
+
+/// Names per info request, keeping the request url well below the AUR limit.
+const INFO_BATCH_SIZE: usize = 100;
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(rename = "type")]
+    response_type: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    results: Vec<Package>,
+}
+
+/// Client for the AUR RPC v5 interface, used instead of the metadata dump when
+/// `metadata_source` is `Rpc`.
+pub struct RpcClient<'a> {
+    base_url: Url,
+    client: HttpClient<'a>,
+}
+
+impl<'a> RpcClient<'a> {
+    pub fn new(config: &Config, warn: Warn<'a>) -> Result<RpcClient<'a>> {
+        let invalid_url = || {
+            Error::new(
+                ErrorKind::Config,
+                format!("The RPC needs an http(s) aur_url, got {}", config.aur_url),
+            )
+        };
+
+        let base_url = Url::parse(&config.aur_url).map_err(|e| invalid_url().with_source(e))?;
+        if base_url.scheme() != "http" && base_url.scheme() != "https" {
+            return Err(invalid_url());
+        }
+
+        Ok(RpcClient {
+            base_url,
+            client: HttpClient::new(config, warn)?,
+        })
+    }
+
+    /// Fetches the packages named exactly `names`, in batches. Unknown names are left out.
+    pub async fn info(&self, names: &[&str]) -> Result<Vec<Package>> {
+        let mut packages: Vec<Package> = Vec::new();
+
+        for batch in names.chunks(INFO_BATCH_SIZE) {
+            let mut url = self.endpoint(&["info"])?;
+            url.query_pairs_mut()
+                .extend_pairs(batch.iter().map(|name| ("arg[]", name)));
+
+            packages.extend(self.request(url).await?);
+        }
+
+        Ok(packages)
+    }
+
+    /// Searches `search_term` on the `by` field.
+    pub async fn search(&self, search_term: &str, by: SearchField) -> Result<Vec<Package>> {
+        let mut url = self.endpoint(&["search", search_term])?;
+        url.query_pairs_mut().append_pair("by", by.as_str());
+
+        self.request(url).await
+    }
+
+    fn endpoint(&self, segments: &[&str]) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::new(ErrorKind::Config, "Invalid aur_url"))?
+            .pop_if_empty()
+            .extend(["rpc", "v5"])
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// Sends the request, the client waits and retries when rate limited.
+    async fn request(&self, url: Url) -> Result<Vec<Package>> {
+        let res = self.client.get(url, HeaderMap::new()).await?;
+        let bytes = self
+            .client
+            .read_body(res.error_for_status()?.into(), &|_, _| {})
+            .await?;
+        let response: RpcResponse = serde_json::from_slice(&bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::Network,
+                "Invalid response received from the AUR RPC",
+            )
+            .with_source(e)
+        })?;
+
+        if response.response_type == "error" {
+            return Err(Error::new(
+                ErrorKind::Network,
+                format!("AUR RPC error: {}", response.error.unwrap_or_default()),
+            ));
+        }
+
+        Ok(response.results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers each connection with the next of `responses`, recording the request targets.
+    async fn serve(responses: Vec<String>) -> (Config, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            aur_url: format!("http://{}", listener.local_addr().unwrap()),
+            ..Config::default()
+        };
+
+        let targets = Arc::new(Mutex::new(Vec::new()));
+        let recorded = targets.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let request = String::from_utf8(request).unwrap();
+                let target = request.split(' ').nth(1).unwrap().to_owned();
+                recorded.lock().unwrap().push(target);
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (config, targets)
+    }
+
+    fn reply(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            status,
+            body.len(),
+            headers,
+            body
+        )
+    }
+
+    fn results(names: &[&str]) -> String {
+        let results: Vec<String> = names
+            .iter()
+            .map(|name| {
+                format!(
+                    r#"{{"Name":"{}","Version":"1.0-1","Popularity":1.5,"URLPath":"/cgit/{}.tar.gz"}}"#,
+                    name, name
+                )
+            })
+            .collect();
+        reply(
+            "200 OK",
+            "Content-Type: application/json\r\n",
+            &format!(
+                r#"{{"version":5,"type":"multiinfo","resultcount":{},"results":[{}]}}"#,
+                names.len(),
+                results.join(",")
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn info_encodes_names_as_arg_array() {
+        let (config, targets) = serve(vec![results(&["yay", "yay-bin"])]).await;
+        let client = RpcClient::new(&config, &|_| {}).unwrap();
+
+        let packages = client.info(&["yay", "yay-bin"]).await.unwrap();
+
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["yay", "yay-bin"]);
+        assert_eq!(
+            *targets.lock().unwrap(),
+            ["/rpc/v5/info?arg%5B%5D=yay&arg%5B%5D=yay-bin"]
+        );
+    }
+
+    #[tokio::test]
+    async fn info_requests_in_batches() {
+        let (config, targets) = serve(vec![results(&["first"]), results(&["second"])]).await;
+        let client = RpcClient::new(&config, &|_| {}).unwrap();
+
+        let names: Vec<String> = (0..INFO_BATCH_SIZE + 50)
+            .map(|i| format!("package-{}", i))
+            .collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let packages = client.info(&names).await.unwrap();
+
+        let found: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(found, ["first", "second"]);
+
+        let targets = targets.lock().unwrap();
+        let batch_sizes: Vec<usize> = targets
+            .iter()
+            .map(|target| target.matches("arg%5B%5D=").count())
+            .collect();
+        assert_eq!(batch_sizes, [INFO_BATCH_SIZE, 50]);
+        assert!(targets[1].starts_with("/rpc/v5/info?arg%5B%5D=package-100&"));
+    }
+
+    #[tokio::test]
+    async fn search_sends_the_field() {
+        let (config, targets) = serve(vec![results(&["yay"])]).await;
+        let client = RpcClient::new(&config, &|_| {}).unwrap();
+
+        let packages = client.search("yay", SearchField::NameDesc).await.unwrap();
+
+        assert_eq!(packages.len(), 1);
+        assert_eq!(
+            *targets.lock().unwrap(),
+            ["/rpc/v5/search/yay?by=name-desc"]
+        );
+    }
+
+    #[tokio::test]
+    async fn error_replies_fail() {
+        let body = r#"{"version":5,"type":"error","resultcount":0,"results":[],"error":"Too many package results."}"#;
+        let (config, _) = serve(vec![reply("200 OK", "", body)]).await;
+        let client = RpcClient::new(&config, &|_| {}).unwrap();
+
+        let error = client.search("a", SearchField::Name).await.unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Network);
+        assert_eq!(
+            error.to_string(),
+            "AUR RPC error: Too many package results."
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_are_retried() {
+        let (config, targets) = serve(vec![
+            reply("429 Too Many Requests", "Retry-After: 1\r\n", ""),
+            results(&["yay"]),
+        ])
+        .await;
+        let warnings = Mutex::new(Vec::new());
+        let warn = |warning: &str| warnings.lock().unwrap().push(warning.to_owned());
+        let client = RpcClient::new(&config, &warn).unwrap();
+
+        let packages = client.info(&["yay"]).await.unwrap();
+
+        assert_eq!(packages.len(), 1);
+        assert_eq!(targets.lock().unwrap().len(), 2);
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("429 Too Many Requests"));
+        assert!(warnings[0].ends_with("Retrying in 1.0s..."));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+use which::which;
+
+/// What to do with an output stream of a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OutputMode {
+    Inherit,
+    Capture,
+    Discard,
+}
+
+impl OutputMode {
+    fn stdio(&self) -> Stdio {
+        match self {
+            OutputMode::Inherit => Stdio::inherit(),
+            OutputMode::Capture => Stdio::piped(),
+            OutputMode::Discard => Stdio::null(),
+        }
+    }
+}
+
+/// An external command such as pacman, makepkg or gpg, described rather than spawned so that it
+/// can be recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    pub envs: Vec<(String, String)>,
+    pub current_dir: Option<PathBuf>,
+    pub stdout: OutputMode,
+    pub stderr: OutputMode,
+}
+
+impl Invocation {
+    /// Captures stdout and discards stderr, like a query would.
+    pub fn new(program: &str) -> Invocation {
+        Invocation {
+            program: program.to_owned(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            current_dir: None,
+            stdout: OutputMode::Capture,
+            stderr: OutputMode::Discard,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Invocation {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: Into<String>>(mut self, args: I) -> Invocation {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Invocation {
+        self.envs.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Invocation {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    pub fn output(mut self, (stdout, stderr): (OutputMode, OutputMode)) -> Invocation {
+        self.stdout = stdout;
+        self.stderr = stderr;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Output {
+    /// Exit code, `None` if the command was killed by a signal.
+    pub code: Option<i32>,
+    /// Empty unless captured.
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    pub fn success(stdout: impl Into<Vec<u8>>) -> Output {
+        Output {
+            code: Some(0),
+            stdout: stdout.into(),
+            stderr: Vec::new(),
+        }
+    }
+
+    pub fn failure(code: i32) -> Output {
+        Output {
+            code: Some(code),
+            ..Output::default()
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Runs every external command, so that they can be redirected or faked.
+pub trait CommandRunner: Send + Sync {
+    /// Runs the command to completion. Fails only if it couldn't be started.
+    fn run(&self, invocation: &Invocation) -> io::Result<Output>;
+
+    /// Whether `program` is installed.
+    fn exists(&self, program: &str) -> bool;
+}
+
+/// Spawns the commands on the system.
+#[derive(Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, invocation: &Invocation) -> io::Result<Output> {
+        let mut command = Command::new(&invocation.program);
+        command
+            .args(&invocation.args)
+            .envs(invocation.envs.iter().map(|(key, value)| (key, value)))
+            .stdout(invocation.stdout.stdio())
+            .stderr(invocation.stderr.stdio());
+
+        if let Some(dir) = &invocation.current_dir {
+            command.current_dir(dir);
+        }
+
+        let output = command.spawn()?.wait_with_output()?;
+
+        Ok(Output {
+            code: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn exists(&self, program: &str) -> bool {
+        which(program).is_ok()
+    }
+}
+
+/// Records the commands instead of running them, answering with canned outputs. Commands
+/// without a response succeed with no output, and every program exists unless set missing.
+#[derive(Debug, Default)]
+pub struct RecordingRunner {
+    responses: Mutex<HashMap<Vec<String>, Output>>,
+    missing: Mutex<Vec<String>>,
+    invocations: Mutex<Vec<Invocation>>,
+}
+
+impl RecordingRunner {
+    pub fn new() -> RecordingRunner {
+        RecordingRunner::default()
+    }
+
+    /// Answers the invocations of `program` with arguments starting with `args` with `output`.
+    /// The response with the most arguments wins.
+    pub fn respond(&self, program: &str, args: &[&str], output: Output) {
+        let key = std::iter::once(program)
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        self.responses.lock().unwrap().insert(key, output);
+    }
+
+    pub fn set_missing(&self, program: &str) {
+        self.missing.lock().unwrap().push(program.to_owned());
+    }
+
+    /// The commands run so far, in order.
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap().clone()
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run(&self, invocation: &Invocation) -> io::Result<Output> {
+        self.invocations.lock().unwrap().push(invocation.clone());
+
+        let command: Vec<&str> = std::iter::once(invocation.program.as_str())
+            .chain(invocation.args.iter().map(String::as_str))
+            .collect();
+
+        let responses = self.responses.lock().unwrap();
+        let output = responses
+            .iter()
+            .filter(|(key, _)| {
+                key.len() <= command.len() && key.iter().zip(&command).all(|(a, b)| a == b)
+            })
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, output)| output.clone())
+            .unwrap_or_else(|| Output::success(Vec::new()));
+
+        Ok(output)
+    }
+
+    fn exists(&self, program: &str) -> bool {
+        !self
+            .missing
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|missing| missing == program)
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Write},
+};
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tar::{Archive, EntryType};
+
+use crate::{
+    config::{expand_path, PACKAGES_CACHE_PATH},
+    database::write_atomic,
+    error::{Error, ErrorKind, Result},
+    package::Package,
+};
+
+pub const SNAPSHOTS_PATH: &str = "~/.cache/aurme/snapshots.json";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SnapshotRecord {
+    pub version: String,
+    pub sha256: String,
+}
+
+/// Hashes of the snapshots in the cache as they were downloaded, so that a snapshot modified
+/// since is never reused or built.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Snapshots(BTreeMap<String, SnapshotRecord>);
+
+impl Snapshots {
+    pub fn read() -> Snapshots {
+        std::fs::read_to_string(expand_path(SNAPSHOTS_PATH))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomic(
+            &expand_path(SNAPSHOTS_PATH),
+            |file| file.write_all(json.as_bytes()),
+            |_| Ok(()),
+        )
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SnapshotRecord> {
+        self.0.get(name)
+    }
+
+    pub fn record(&mut self, package: &Package, sha256: String) {
+        self.0.insert(
+            package.name.to_owned(),
+            SnapshotRecord {
+                version: package.version.to_owned(),
+                sha256,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.remove(name);
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Checks that the cached snapshot of `name` still has the recorded hash, and that the files
+/// extracted from it weren't modified either.
+pub fn verify_snapshot(name: &str, snapshots: &Snapshots) -> Result<()> {
+    let invalid = |message: String| {
+        Error::new(ErrorKind::Extraction, message).with_hint(format!(
+            "Clear the cache with aurme -Sc {} and try again",
+            name
+        ))
+    };
+
+    let record = snapshots
+        .get(name)
+        .ok_or_else(|| invalid(format!("No recorded hash for the snapshot of \"{}\"", name)))?;
+
+    let cache_path = expand_path(PACKAGES_CACHE_PATH);
+    let tarball = std::fs::read(cache_path.join(format!("{}.tar.gz", name))).map_err(|e| {
+        invalid(format!("Failed to read the snapshot of \"{}\"", name)).with_source(e)
+    })?;
+
+    if sha256(&tarball) != record.sha256 {
+        return Err(invalid(format!(
+            "The snapshot of \"{}\" was modified since it was downloaded",
+            name
+        )));
+    }
+
+    let invalid_archive =
+        |e: std::io::Error| invalid(format!("Invalid snapshot of \"{}\"", name)).with_source(e);
+
+    let mut archive = Archive::new(GzDecoder::new(&tarball[..]));
+    for entry in archive.entries().map_err(invalid_archive)? {
+        let mut entry = entry.map_err(invalid_archive)?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+
+        let path = cache_path.join(entry.path().map_err(invalid_archive)?);
+        let mut expected: Vec<u8> = Vec::new();
+        entry.read_to_end(&mut expected).map_err(invalid_archive)?;
+
+        let mut actual: Vec<u8> = Vec::new();
+        let unchanged = File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut actual))
+            .is_ok_and(|_| actual == expected);
+
+        if !unchanged {
+            return Err(invalid(format!(
+                "{} was modified or removed since the snapshot of \"{}\" was downloaded",
+                path.display(),
+                name
+            )));
+        }
+    }
+
+    Ok(())
+}
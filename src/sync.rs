@@ -0,0 +1,132 @@
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::expand_path,
+    database::{write_atomic, Database, DB_PATH},
+    error::Result,
+    package::Package,
+};
+
+pub const SYNC_STATE_PATH: &str = "~/.cache/aurme/sync.json";
+
+/// Source and validators of the last successful database download, sent back to only download
+/// it again when it changed, and when the database was last known to be fresh.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SyncState {
+    #[serde(default)]
+    pub source: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    #[serde(default)]
+    pub synced_at: Option<u64>,
+}
+
+impl SyncState {
+    pub fn read() -> SyncState {
+        std::fs::read_to_string(expand_path(SYNC_STATE_PATH))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Age of the database, falling back to the database file modification time for databases
+    /// synced before the sync time was recorded.
+    pub fn database_age(&self) -> Option<Duration> {
+        let synced_at = match self.synced_at {
+            Some(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+            None => std::fs::metadata(expand_path(DB_PATH))
+                .and_then(|metadata| metadata.modified())
+                .ok()?,
+        };
+
+        SystemTime::now().duration_since(synced_at).ok()
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomic(
+            &expand_path(SYNC_STATE_PATH),
+            |file| file.write_all(json.as_bytes()),
+            |_| Ok(()),
+        )
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// What a database sync did.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SyncOutcome {
+    /// The database didn't change since the last sync.
+    UpToDate,
+    /// A new database was written, `changes` is `None` if there was no previous one.
+    Updated { changes: Option<DatabaseChanges> },
+}
+
+#[derive(Debug, Default)]
+pub struct DatabaseChanges {
+    pub new: Vec<String>,
+    pub removed: Vec<String>,
+    pub updated: Vec<(String, String, String)>,
+    pub orphaned: Vec<String>,
+    pub flagged: Vec<String>,
+}
+
+impl DatabaseChanges {
+    /// Compares the previous database with the new one.
+    pub fn compare(old: &Database, new: &Database) -> DatabaseChanges {
+        let mut changes = DatabaseChanges::default();
+        let mut old_packages = old.packages().peekable();
+        let mut new_packages = new.packages().peekable();
+
+        loop {
+            match (old_packages.peek(), new_packages.peek()) {
+                (None, None) => break,
+                (Some(old), Some(new)) if old.name == new.name => {
+                    changes.compare_package(old, new);
+                    old_packages.next();
+                    new_packages.next();
+                }
+                (Some(old), Some(new)) if old.name < new.name => {
+                    changes.removed.push(old.name.to_owned());
+                    old_packages.next();
+                }
+                (Some(old), None) => {
+                    changes.removed.push(old.name.to_owned());
+                    old_packages.next();
+                }
+                (_, Some(new)) => {
+                    changes.new.push(new.name.to_owned());
+                    new_packages.next();
+                }
+            }
+        }
+
+        changes
+    }
+
+    fn compare_package(&mut self, old: &Package, new: &Package) {
+        if old.version != new.version {
+            self.updated.push((
+                new.name.to_owned(),
+                old.version.to_owned(),
+                new.version.to_owned(),
+            ));
+        }
+        if old.maintainer.is_some() && new.maintainer.is_none() {
+            self.orphaned.push(new.name.to_owned());
+        }
+        if old.out_of_date.is_none() && new.out_of_date.is_some() {
+            self.flagged.push(new.name.to_owned());
+        }
+    }
+}
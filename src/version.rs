@@ -0,0 +1,214 @@
+use std::cmp::Ordering;
+
+/// Compares two package versions the way pacman's `vercmp` does, e.g. `1.0-2` is older than
+/// `1:0.9-1` because of the epoch, and `1.0rc1` is older than `1.0`. The pkgrel is only compared
+/// when both versions have one.
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let (epoch_a, version_a, release_a) = parse_evr(a);
+    let (epoch_b, version_b, release_b) = parse_evr(b);
+
+    rpmvercmp(epoch_a, epoch_b)
+        .then_with(|| rpmvercmp(version_a, version_b))
+        .then_with(|| match (release_a, release_b) {
+            (Some(release_a), Some(release_b)) => rpmvercmp(release_a, release_b),
+            _ => Ordering::Equal,
+        })
+}
+
+/// Whether `version` meets `constraint`, such as `>=1.2` or `=2.0-1`. An empty constraint is met
+/// by any version, including none at all, while any other one needs a version.
+pub fn meets_constraint(version: Option<&str>, constraint: &str) -> bool {
+    if constraint.is_empty() {
+        return true;
+    }
+
+    let (operator, wanted) = match constraint.get(..2) {
+        Some(operator @ ("<=" | ">=")) => (operator, &constraint[2..]),
+        _ => constraint.split_at(1),
+    };
+
+    let Some(version) = version else {
+        return false;
+    };
+    let ordering = vercmp(version, wanted);
+
+    match operator {
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        "=" => ordering.is_eq(),
+        ">=" => ordering.is_ge(),
+        ">" => ordering.is_gt(),
+        _ => false,
+    }
+}
+
+/// Splits `[epoch:]version[-release]`, the epoch defaulting to `0`.
+fn parse_evr(evr: &str) -> (&str, &str, Option<&str>) {
+    let digits = evr.bytes().take_while(u8::is_ascii_digit).count();
+    let (epoch, rest) = match evr[digits..].starts_with(':') {
+        true if digits == 0 => ("0", &evr[1..]),
+        true => (&evr[..digits], &evr[digits + 1..]),
+        false => ("0", evr),
+    };
+
+    match rest.rsplit_once('-') {
+        Some((version, release)) => (epoch, version, Some(release)),
+        None => (epoch, rest, None),
+    }
+}
+
+/// Port of the `rpmvercmp` of libalpm, comparing alternating runs of digits and letters, the
+/// separators in between only counting by their length.
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut one, mut two) = (0, 0);
+
+    while one < a.len() && two < b.len() {
+        let separator_start = (one, two);
+        while one < a.len() && !a[one].is_ascii_alphanumeric() {
+            one += 1;
+        }
+        while two < b.len() && !b[two].is_ascii_alphanumeric() {
+            two += 1;
+        }
+
+        if one == a.len() || two == b.len() {
+            break;
+        }
+
+        // Different separator lengths decide on their own
+        let separators = (one - separator_start.0, two - separator_start.1);
+        if separators.0 != separators.1 {
+            return separators.0.cmp(&separators.1);
+        }
+
+        let is_num = a[one].is_ascii_digit();
+        let in_segment = |byte: &u8| match is_num {
+            true => byte.is_ascii_digit(),
+            false => byte.is_ascii_alphabetic(),
+        };
+        let end_one = one + a[one..].iter().take_while(|byte| in_segment(byte)).count();
+        let end_two = two + b[two..].iter().take_while(|byte| in_segment(byte)).count();
+
+        // Segments of different types, numbers are newer
+        if end_two == two {
+            return match is_num {
+                true => Ordering::Greater,
+                false => Ordering::Less,
+            };
+        }
+
+        let (mut segment_one, mut segment_two) = (&a[one..end_one], &b[two..end_two]);
+        if is_num {
+            fn trim_zeros(segment: &[u8]) -> &[u8] {
+                let zeros = segment.iter().take_while(|byte| **byte == b'0').count();
+                &segment[zeros..]
+            }
+            segment_one = trim_zeros(segment_one);
+            segment_two = trim_zeros(segment_two);
+
+            let by_length = segment_one.len().cmp(&segment_two.len());
+            if by_length.is_ne() {
+                return by_length;
+            }
+        }
+
+        let ordering = segment_one.cmp(segment_two);
+        if ordering.is_ne() {
+            return ordering;
+        }
+
+        (one, two) = (end_one, end_two);
+    }
+
+    let (rest_one, rest_two) = (&a[one..], &b[two..]);
+    if rest_one.is_empty() && rest_two.is_empty() {
+        return Ordering::Equal;
+    }
+
+    // A remaining alpha segment never beats an empty one, e.g. `1.0rc` is older than `1.0`
+    let alpha = |rest: &[u8]| rest.first().is_some_and(u8::is_ascii_alphabetic);
+    if (rest_one.is_empty() && !alpha(rest_two)) || alpha(rest_one) {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cases of the `vercmp` test suite of pacman.
+    #[test]
+    fn versions_compare_like_pacman() {
+        let cases = [
+            ("1.5.0", "1.5.0", Ordering::Equal),
+            ("1.5.1", "1.5.0", Ordering::Greater),
+            ("1.5.1", "1.5", Ordering::Greater),
+            ("1.5.0-1", "1.5.0-1", Ordering::Equal),
+            ("1.5.0-1", "1.5.0-2", Ordering::Less),
+            ("1.5.0-1", "1.5.1-1", Ordering::Less),
+            ("1.5.0-2", "1.5.1-1", Ordering::Less),
+            ("1.5-1", "1.5", Ordering::Equal),
+            ("1.1-1", "1.1", Ordering::Equal),
+            ("1.0-1", "1.1", Ordering::Less),
+            ("1.1-1", "1.0", Ordering::Greater),
+            ("1.5b-1", "1.5-1", Ordering::Less),
+            ("1.5b", "1.5", Ordering::Less),
+            ("1.5b-1", "1.5", Ordering::Less),
+            ("1.5b", "1.5.1", Ordering::Less),
+            ("1.0", "1.0.a", Ordering::Less),
+            ("1.0.a", "1.0.1", Ordering::Less),
+            ("1.0a", "1.0alpha", Ordering::Less),
+            ("1.0alpha", "1.0b", Ordering::Less),
+            ("1.0b", "1.0beta", Ordering::Less),
+            ("1.0beta", "1.0rc", Ordering::Less),
+            ("1.0rc", "1.0", Ordering::Less),
+            ("1.5.a", "1.5", Ordering::Greater),
+            ("1.5", "1.5.a", Ordering::Less),
+            ("1.5..a", "1.5.a", Ordering::Greater),
+            ("1.5a", "1.5.a", Ordering::Less),
+            ("1.5", "1.5.1", Ordering::Less),
+            ("1.5.1", "1.5.b", Ordering::Greater),
+            ("1.5.b", "1.5.1", Ordering::Less),
+            ("0:1.0", "1.0", Ordering::Equal),
+            ("1:1.0", "1.0", Ordering::Greater),
+            ("1:1.0", "1:1.1", Ordering::Less),
+            ("1:1.0", "2:1.1", Ordering::Less),
+            ("1:1.0", "0:1.0", Ordering::Greater),
+            ("2:1.0", "1:2.0", Ordering::Greater),
+            ("1:1.0-1", "2.0-1", Ordering::Greater),
+            ("r12.g1a2b3c-1", "r9.g9f8e7d-1", Ordering::Greater),
+            ("1.001", "1.1", Ordering::Equal),
+            ("1.0010", "1.1", Ordering::Greater),
+        ];
+
+        for (a, b, expected) in cases {
+            assert_eq!(vercmp(a, b), expected, "{} vs {}", a, b);
+            assert_eq!(vercmp(b, a), expected.reverse(), "{} vs {}", b, a);
+        }
+    }
+
+    #[test]
+    fn constraints_are_met() {
+        assert!(meets_constraint(Some("3-1"), ""));
+        assert!(meets_constraint(None, ""));
+        assert!(!meets_constraint(None, ">=1"));
+        assert!(meets_constraint(Some("1.9-1"), "<2"));
+        assert!(!meets_constraint(Some("3-1"), "<2"));
+        assert!(meets_constraint(Some("2.0-1"), "<=2.0"));
+        assert!(meets_constraint(Some("2.0-3"), "=2.0"));
+        assert!(!meets_constraint(Some("2.0-3"), "=2.0-1"));
+        assert!(meets_constraint(Some("1:1.0-1"), ">=2.0"));
+        assert!(!meets_constraint(Some("2.0"), ">2.0"));
+    }
+}
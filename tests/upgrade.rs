@@ -0,0 +1,156 @@
+//! Drives the upgrade flow of the library against a `file://` AUR and a `RecordingRunner`, with
+//! `HOME` set to a temporary folder so that the cache of the user is left alone.
+
+use std::{
+    fs::File,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use aurme::{
+    Aurme, Config, InstallEvent, InstallObserver, Invocation, Output, PgpKey, RecordingRunner,
+};
+use flate2::{write::GzEncoder, Compression};
+
+const KEY: &str = "F4A7678C9C6B0E62ECE3FE1D76FE8B1BB79FB2D3";
+
+/// Writes the metadata dump and the snapshot of `yay` 12.3.5-1 to `aur`.
+fn write_aur(aur: &Path) {
+    std::fs::create_dir_all(aur.join("cgit")).unwrap();
+
+    let dump =
+        r#"[{"Name":"yay","Version":"12.3.5-1","Popularity":10.5,"URLPath":"/cgit/yay.tar.gz"}]"#;
+    let mut encoder = GzEncoder::new(
+        File::create(aur.join("packages-meta-ext-v1.json.gz")).unwrap(),
+        Compression::default(),
+    );
+    std::io::Write::write_all(&mut encoder, dump.as_bytes()).unwrap();
+    encoder.finish().unwrap();
+
+    let encoder = GzEncoder::new(
+        File::create(aur.join("cgit/yay.tar.gz")).unwrap(),
+        Compression::default(),
+    );
+    let mut archive = tar::Builder::new(encoder);
+    for (name, content) in [
+        (
+            "yay/PKGBUILD",
+            "pkgname=yay\npkgver=12.3.5\npkgrel=1\n".to_owned(),
+        ),
+        (
+            "yay/.SRCINFO",
+            format!("pkgbase = yay\n\tvalidpgpkeys = {}\n", KEY),
+        ),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, name, content.as_bytes())
+            .unwrap();
+    }
+    archive.into_inner().unwrap().finish().unwrap();
+}
+
+#[derive(Default)]
+struct Observer {
+    installed: Mutex<Vec<String>>,
+    keys: Mutex<Vec<String>>,
+}
+
+impl InstallObserver for Observer {
+    fn event(&self, event: InstallEvent) {
+        if let InstallEvent::Installed { package } = event {
+            self.installed.lock().unwrap().push(package.name.clone());
+        }
+    }
+
+    fn confirm_key_import(&self, keys: &[PgpKey]) -> bool {
+        let mut asked = self.keys.lock().unwrap();
+        asked.extend(keys.iter().map(|key| key.key.clone()));
+        true
+    }
+}
+
+fn command(invocation: &Invocation) -> Vec<&str> {
+    std::iter::once(invocation.program.as_str())
+        .chain(invocation.args.iter().map(String::as_str))
+        .collect()
+}
+
+#[tokio::test]
+async fn outdated_packages_are_built_with_their_keys_imported() {
+    let home = std::env::temp_dir().join(format!("aurme-upgrade-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&home);
+    std::env::set_var("HOME", &home);
+
+    let aur = home.join("aur");
+    write_aur(&aur);
+
+    let runner = Arc::new(RecordingRunner::new());
+    runner.respond("pacman", &["-Qm"], Output::success("yay 12.3.4-1\n"));
+    runner.respond(
+        "pacman",
+        &["-Qi"],
+        Output::success(
+            "Name            : yay\nVersion         : 12.3.4-1\nConflicts With  : None\n",
+        ),
+    );
+    runner.respond("gpg", &["--list-keys"], Output::failure(2));
+    runner.respond(
+        "makepkg",
+        &["--packagelist"],
+        Output::success("yay-12.3.5-1-x86_64.pkg.tar.zst\n"),
+    );
+
+    let config = Config {
+        aur_url: format!("file://{}", aur.display()),
+        keyserver: Some("hkps://keyserver.ubuntu.com".to_owned()),
+        ..Config::default()
+    };
+    let aurme = Aurme::with_runner(config, runner.clone());
+
+    aurme.sync(|_, _| {}).await.unwrap();
+
+    let upgrades = aurme.upgrades(&[]).await.unwrap();
+    assert_eq!(upgrades.outdated.len(), 1);
+    assert_eq!(upgrades.outdated[0].installed.version, "12.3.4-1");
+    assert_eq!(upgrades.outdated[0].available.version, "12.3.5-1");
+
+    let plan = aurme.plan_upgrades(&upgrades).unwrap();
+    assert!(plan.conflicts.installed.is_empty());
+
+    let observer = Observer::default();
+    aurme.install(&plan, &observer).await.unwrap();
+
+    assert_eq!(*observer.keys.lock().unwrap(), [KEY]);
+    assert_eq!(*observer.installed.lock().unwrap(), ["yay"]);
+
+    let invocations = runner.invocations();
+    let commands: Vec<Vec<&str>> = invocations.iter().map(command).collect();
+    assert_eq!(
+        commands,
+        [
+            vec!["pacman", "-Qm"],
+            vec!["pacman", "-Qi"],
+            vec!["gpg", "--list-keys", KEY],
+            vec![
+                "gpg",
+                "--keyserver",
+                "hkps://keyserver.ubuntu.com",
+                "--recv-keys",
+                KEY
+            ],
+            vec!["makepkg", "-s"],
+            vec!["makepkg", "--packagelist"],
+            vec!["sudo", "pacman", "-U", "yay-12.3.5-1-x86_64.pkg.tar.zst"],
+        ]
+    );
+    assert_eq!(
+        invocations[4].current_dir,
+        Some(home.join(".cache/aurme/packages/yay"))
+    );
+
+    std::fs::remove_dir_all(&home).unwrap();
+}